@@ -0,0 +1,313 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveDateTime;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::{INPUT_DATE_FORMAT, SACCT_DELIMITER};
+
+/// Extension of the files read by [`Replay`]
+const FIXTURE_EXTENSION: &str = "sacct";
+/// Extension of the batch scripts read by [`Replay`], named after their job
+const BATCH_SCRIPT_EXTENSION: &str = "sh";
+
+/// Line under sacct's `Batch Script for <jobid>` heading, made of this character only
+const BATCH_SCRIPT_RULE: char = '-';
+
+/// Source of job accounting data. Every method returns `sacct -P -n` style text: one job or
+/// step per line, with the requested `fields` in order, separated by [`SACCT_DELIMITER`].
+pub(crate) trait Backend {
+    /// Jobs (and their steps) belonging to `user` that were active after `window_start`
+    fn query_jobs(
+        &self,
+        fields: &[&str],
+        window_start: NaiveDateTime,
+        user: &str,
+    ) -> Result<String>;
+
+    /// The given `fields` of a single job and its steps
+    fn job_details(&self, jobid: &str, fields: &[&str]) -> Result<String>;
+
+    /// The batch script `jobid` was submitted with, if SLURM still has it
+    fn batch_script(&self, jobid: &str) -> Result<Option<String>>;
+}
+
+/// Queries the live SLURM accounting database through `sacct`
+pub(crate) struct Sacct;
+
+impl Sacct {
+    fn run(args: &[String]) -> Result<String> {
+        let output = Command::new("sacct")
+            .args(args)
+            .output()
+            .context("failed to execute sacct")?;
+
+        let bytes = if output.status.success() {
+            output.stdout
+        } else {
+            output.stderr
+        };
+        String::from_utf8(bytes).context("sacct output contained invalid UTF-8")
+    }
+
+    /// Asks slurmctld for the script of a job it still knows about
+    fn scontrol_batch_script(jobid: &str) -> Option<String> {
+        let output = Command::new("scontrol")
+            .args(["write", "batch_script", jobid, "-"])
+            .output()
+            .ok()?;
+        let script = String::from_utf8(output.stdout).ok()?;
+        (output.status.success() && !script.trim().is_empty()).then_some(script)
+    }
+
+    /// Asks the accounting database for the script, which it keeps only if the cluster stores
+    /// job scripts (`AccountingStoreFlags=job_script`)
+    fn sacct_batch_script(jobid: &str) -> Result<Option<String>> {
+        let output = Command::new("sacct")
+            .args(["-n", "-j", jobid, "--batch-script"])
+            .output()
+            .context("failed to execute sacct")?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let output =
+            String::from_utf8(output.stdout).context("sacct output contained invalid UTF-8")?;
+        Ok(strip_batch_script_heading(&output))
+    }
+}
+
+/// The script in `sacct --batch-script` output, without the heading sacct puts above it
+fn strip_batch_script_heading(output: &str) -> Option<String> {
+    let mut lines = output.lines();
+    let rule = lines
+        .position(|line| !line.is_empty() && line.trim().chars().all(|c| c == BATCH_SCRIPT_RULE));
+    let script: Vec<_> = match rule {
+        Some(_) => lines.collect(),
+        None => output.lines().collect(),
+    };
+    let script = script.join("\n");
+    // jobs without a stored script are reported as `NONE`
+    (!script.trim().is_empty() && script.trim() != "NONE").then(|| script + "\n")
+}
+
+impl Backend for Sacct {
+    fn query_jobs(
+        &self,
+        fields: &[&str],
+        window_start: NaiveDateTime,
+        user: &str,
+    ) -> Result<String> {
+        Sacct::run(&[
+            "-u".to_string(),
+            user.to_string(),
+            "-n".to_string(),
+            "-P".to_string(),
+            "-S".to_string(),
+            window_start.format(INPUT_DATE_FORMAT).to_string(),
+            format!("--format={}", fields.join(",")),
+        ])
+    }
+
+    fn job_details(&self, jobid: &str, fields: &[&str]) -> Result<String> {
+        Sacct::run(&[
+            "-n".to_string(),
+            "-P".to_string(),
+            "-j".to_string(),
+            jobid.to_string(),
+            format!("--format={}", fields.join(",")),
+        ])
+    }
+
+    fn batch_script(&self, jobid: &str) -> Result<Option<String>> {
+        match Sacct::scontrol_batch_script(jobid) {
+            Some(script) => Ok(Some(script)),
+            None => Sacct::sacct_batch_script(jobid),
+        }
+    }
+}
+
+/// Replays sacct output recorded in a directory instead of talking to SLURM.
+///
+/// Every `*.sacct` file in the directory is read in name order. Each file must start with the
+/// header line that `sacct -P` prints (i.e. recorded *without* `-n`), for example
+///
+/// ```text
+/// sacct -P -u $USER -S 2025-01-01 --format=jobid,jobname,alloccpus,elapsed,start,end,state,workdir,totalcpu,maxrss,reqmem,timelimit,exitcode,derivedexitcode,submitline > jobs.sacct
+/// ```
+///
+/// Columns are matched to the requested fields by name (case-insensitively), so fixtures may
+/// contain more fields than are asked for, in any order. Fields missing from a fixture are
+/// replayed as empty values. The time window and user of a query are ignored: everything that
+/// was recorded is replayed.
+///
+/// Batch scripts are read from `<jobid>.sh`, falling back to `<base>.sh` for array tasks.
+pub(crate) struct Replay {
+    dir: PathBuf,
+}
+
+impl Replay {
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Self {
+        Replay { dir: dir.into() }
+    }
+
+    /// Every recorded record, re-projected onto `fields`, optionally only for lines whose jobid
+    /// satisfies `keep`
+    fn replay(&self, fields: &[&str], keep: impl Fn(&str) -> bool) -> Result<String> {
+        let mut paths: Vec<_> = fs::read_dir(&self.dir)
+            .with_context(|| format!("unable to read replay directory {}", self.dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == FIXTURE_EXTENSION))
+            .collect();
+        paths.sort();
+
+        let mut output = String::new();
+        for path in paths {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("unable to read fixture {}", path.display()))?;
+            let mut lines = contents.lines();
+            let Some(header) = lines.next() else {
+                continue;
+            };
+            let columns: Vec<_> = header.split(SACCT_DELIMITER).collect();
+            let find = |name: &str| columns.iter().position(|c| c.eq_ignore_ascii_case(name));
+            let jobid_column = find("jobid")
+                .ok_or_else(|| anyhow!("fixture {} has no JobID column", path.display()))?;
+            let projection: Vec<_> = fields.iter().map(|field| find(field)).collect();
+
+            for line in lines.filter(|line| !line.trim().is_empty()) {
+                let values: Vec<_> = line.split(SACCT_DELIMITER).collect();
+                if !values.get(jobid_column).is_some_and(|jobid| keep(jobid)) {
+                    continue;
+                }
+                let projected: Vec<_> = projection
+                    .iter()
+                    .map(|column| column.and_then(|c| values.get(c).copied()).unwrap_or(""))
+                    .collect();
+                output.push_str(&projected.join(&SACCT_DELIMITER.to_string()));
+                output.push('\n');
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl Backend for Replay {
+    fn query_jobs(
+        &self,
+        fields: &[&str],
+        _window_start: NaiveDateTime,
+        _user: &str,
+    ) -> Result<String> {
+        self.replay(fields, |_| true)
+    }
+
+    fn job_details(&self, jobid: &str, fields: &[&str]) -> Result<String> {
+        // match the job itself and its steps (`<jobid>.batch`, `<jobid>.0`, ...), and like sacct
+        // all tasks of an array job or components of a heterogeneous job given by its base ID
+        let is_base_id = !jobid.contains(['_', '+']);
+        self.replay(fields, |line_jobid| {
+            line_jobid == jobid
+                || line_jobid.strip_prefix(jobid).is_some_and(|rest| {
+                    rest.starts_with('.') || (is_base_id && rest.starts_with(['_', '+']))
+                })
+        })
+    }
+
+    fn batch_script(&self, jobid: &str) -> Result<Option<String>> {
+        let base = jobid.split_once('_').map_or(jobid, |(base, _)| base);
+        for name in [jobid, base] {
+            let path = self.dir.join(format!("{name}.{BATCH_SCRIPT_EXTENSION}"));
+            if path.is_file() {
+                let script = fs::read_to_string(&path)
+                    .with_context(|| format!("unable to read batch script {}", path.display()))?;
+                return Ok(Some(script));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The jobs recorded in `tests/fixtures/basic`, which the tests of every module replay
+#[cfg(test)]
+pub(crate) fn replay_fixtures() -> Replay {
+    Replay::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/basic"))
+}
+
+/// The jobs in [`replay_fixtures`], with the default columns and their exit codes
+#[cfg(test)]
+pub(crate) fn replayed_jobs() -> Vec<crate::Job> {
+    let fields = [&crate::FORMAT_CMD[..], &["exitcode"]].concat();
+    let now = chrono::Local::now().naive_local();
+    crate::collect_jobs(&replay_fixtures(), now, "me", &[], &fields).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_projects_requested_fields() {
+        let now = chrono::Local::now().naive_local();
+        let output = replay_fixtures()
+            .query_jobs(&["state", "jobid"], now, "me")
+            .unwrap();
+        let first = output.lines().next().unwrap();
+        assert_eq!(first, "COMPLETED|56938942");
+    }
+
+    #[test]
+    fn replay_fills_missing_fields_with_blanks() {
+        let now = chrono::Local::now().naive_local();
+        let output = replay_fixtures()
+            .query_jobs(&["jobid", "notafield"], now, "me")
+            .unwrap();
+        assert!(output.lines().all(|line| line.ends_with('|')));
+    }
+
+    #[test]
+    fn replay_batch_scripts() {
+        let script = replay_fixtures().batch_script("56938943").unwrap().unwrap();
+        assert!(script.starts_with("#!/bin/bash"));
+        assert_eq!(
+            replay_fixtures().batch_script("56938944_2").unwrap(),
+            replay_fixtures().batch_script("56938944").unwrap()
+        );
+        assert_eq!(replay_fixtures().batch_script("56938942").unwrap(), None);
+    }
+
+    #[test]
+    fn batch_script_heading_is_stripped() {
+        let output = "Batch Script for 123\n----------\n#!/bin/bash\n#SBATCH -t 10\n\nsrun a.out\n";
+        assert_eq!(
+            strip_batch_script_heading(output).unwrap(),
+            "#!/bin/bash\n#SBATCH -t 10\n\nsrun a.out\n"
+        );
+        assert_eq!(
+            strip_batch_script_heading("Batch Script for 123\n----------\nNONE\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn replay_job_details_matches_job_and_steps() {
+        let output = replay_fixtures()
+            .job_details("56938942", &["jobid"])
+            .unwrap();
+        let ids: Vec<_> = output.lines().collect();
+        assert_eq!(ids, ["56938942", "56938942.batch", "56938942.extern"]);
+
+        let output = replay_fixtures()
+            .job_details("56938944", &["jobid"])
+            .unwrap();
+        let ids: Vec<_> = output.lines().collect();
+        assert_eq!(
+            ids,
+            [
+                "56938944_1",
+                "56938944_1.batch",
+                "56938944_2",
+                "56938944_2.batch"
+            ]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use chrono::NaiveDateTime;
+use colored::{ColoredString, Colorize};
+
+use crate::efficiency::{Efficiency, RESOURCE_FIELDS, colour_ratio, colour_time_use, format_ratio};
+use crate::exit_code::{EXIT_FIELDS, ExitCode, explain};
+use crate::job_state::JobState;
+use crate::{FORMAT_CMD, INPUT_DATE_FORMAT, Job, START_END_FORMAT, format_duration};
+
+/// How the values of a sacct field are parsed and displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FieldKind {
+    /// Shown as is
+    Text,
+    /// Whole numbers such as CPU or node counts
+    Count,
+    /// `[DD-][HH:]MM:SS` durations
+    Duration,
+    /// Timestamps, shown in the short `START_END_FORMAT`
+    Timestamp,
+    /// Sizes such as `123456K`, shown with binary units
+    Memory,
+    /// Job states, coloured by outcome
+    State,
+    /// Efficiency ratios computed by `jobs_done` rather than read from sacct
+    Ratio,
+    /// Share of the time limit used, computed by `jobs_done`
+    TimeUse,
+    /// Explanations of how a job ended, computed by `jobs_done`
+    Explanation,
+}
+
+/// A sacct field known to `jobs_done`
+struct FieldSpec {
+    name: &'static str,
+    header: &'static str,
+    kind: FieldKind,
+}
+
+const fn spec(name: &'static str, header: &'static str, kind: FieldKind) -> FieldSpec {
+    FieldSpec { name, header, kind }
+}
+
+/// Every field with a dedicated header or formatting. Names are sacct's `--format` names
+/// in lower case.
+const KNOWN_FIELDS: &[FieldSpec] = &[
+    spec("jobid", "Job ID", FieldKind::Text),
+    spec("jobname", "Job Name", FieldKind::Text),
+    spec("alloccpus", "CPUs", FieldKind::Count),
+    spec("elapsed", "Elapsed", FieldKind::Duration),
+    spec("start", "Start", FieldKind::Timestamp),
+    spec("end", "End", FieldKind::Timestamp),
+    spec("state", "State", FieldKind::State),
+    spec("submit", "Submit", FieldKind::Timestamp),
+    spec("eligible", "Eligible", FieldKind::Timestamp),
+    spec("partition", "Partition", FieldKind::Text),
+    spec("account", "Account", FieldKind::Text),
+    spec("qos", "QOS", FieldKind::Text),
+    spec("user", "User", FieldKind::Text),
+    spec("cluster", "Cluster", FieldKind::Text),
+    spec("nnodes", "Nodes", FieldKind::Count),
+    spec("ncpus", "CPUs", FieldKind::Count),
+    spec("nodelist", "Nodes", FieldKind::Text),
+    spec("maxrss", "MaxRSS", FieldKind::Memory),
+    spec("maxvmsize", "MaxVMSize", FieldKind::Memory),
+    spec("reqmem", "ReqMem", FieldKind::Memory),
+    spec("timelimit", "Time Limit", FieldKind::Duration),
+    spec("totalcpu", "Total CPU", FieldKind::Duration),
+    spec("cputime", "CPU Time", FieldKind::Duration),
+    spec("exitcode", "Exit Code", FieldKind::Text),
+    spec("derivedexitcode", "Derived Exit", FieldKind::Text),
+    spec("workdir", "Work Dir", FieldKind::Text),
+    spec("cpueff", "CPU Eff", FieldKind::Ratio),
+    spec("memeff", "Mem Eff", FieldKind::Ratio),
+    spec("timeeff", "Time Used", FieldKind::TimeUse),
+    spec("reason", "Reason", FieldKind::Explanation),
+];
+
+/// Columns added by `--efficiency`
+pub(crate) const EFFICIENCY_COLUMNS: [&str; 3] = ["cpueff", "memeff", "timeeff"];
+/// Columns added by `--steps`, to tell which step failed and how much memory it used
+pub(crate) const STEP_COLUMNS: [&str; 2] = ["exitcode", "maxrss"];
+
+/// A column of the job table, backed by one sacct field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Column {
+    /// sacct field name, in lower case
+    pub(crate) name: String,
+    pub(crate) header: String,
+    pub(crate) kind: FieldKind,
+}
+
+impl Column {
+    /// Looks `name` up in the registry of known fields. Unknown fields are passed through to
+    /// sacct as is and shown as plain text.
+    pub(crate) fn new(name: &str) -> Self {
+        let name = name.trim().to_lowercase();
+        match KNOWN_FIELDS.iter().find(|spec| spec.name == name) {
+            Some(spec) => Column {
+                name,
+                header: spec.header.to_string(),
+                kind: spec.kind,
+            },
+            None => Column {
+                header: name.clone(),
+                name,
+                kind: FieldKind::Text,
+            },
+        }
+    }
+
+    /// Uncoloured, machine-friendly value of this column for `job`: timestamps are ISO 8601 and
+    /// missing values are empty
+    pub(crate) fn plain_value(&self, job: &Job) -> String {
+        let timestamp = |date: Option<NaiveDateTime>| {
+            date.map(|d| d.format(INPUT_DATE_FORMAT).to_string())
+                .unwrap_or_default()
+        };
+        let duration = |seconds: Option<u64>| seconds.map(format_duration).unwrap_or_default();
+        let memory = |bytes: Option<u64>| bytes.map(format_memory).unwrap_or_default();
+        let ratio = |ratio: Option<f64>| ratio.map(format_ratio).unwrap_or_default();
+        let exit_code = |code: Option<ExitCode>| code.map(|c| c.to_string()).unwrap_or_default();
+        match self.name.as_str() {
+            "jobid" => job.jobid_display(),
+            "jobname" => job.jobname.clone(),
+            "alloccpus" => job.alloccpus.to_string(),
+            "elapsed" => job.elapsed.clone(),
+            "start" => timestamp(job.start),
+            "end" => timestamp(job.end),
+            "state" => job.state.to_string(),
+            "totalcpu" => duration(job.resources.total_cpu),
+            "maxrss" => memory(job.resources.max_rss),
+            "reqmem" => memory(job.resources.req_mem),
+            "timelimit" => duration(job.resources.time_limit),
+            "cpueff" => ratio(Efficiency::of(job).cpu),
+            "memeff" => ratio(Efficiency::of(job).memory),
+            "timeeff" => ratio(Efficiency::of(job).time),
+            "exitcode" => exit_code(job.exit_codes.exit_code),
+            "derivedexitcode" => exit_code(job.exit_codes.derived_exit_code),
+            "reason" => explain(&job.state, &job.exit_codes).unwrap_or_default(),
+            name => job.extra.get(name).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Whether this column is computed from other fields instead of being queried from sacct
+    fn is_derived(&self) -> bool {
+        matches!(
+            self.kind,
+            FieldKind::Ratio | FieldKind::TimeUse | FieldKind::Explanation
+        )
+    }
+
+    /// Value of this column for `job` as shown in the table
+    pub(crate) fn display_value(&self, job: &Job) -> ColoredString {
+        match self.name.as_str() {
+            "start" => match job.start {
+                Some(start) => start.format(START_END_FORMAT).to_string().white(),
+                None => "NOT STARTED".yellow(),
+            },
+            "end" => match job.end {
+                Some(end) => end.format(START_END_FORMAT).to_string().white(),
+                None => "UNKNOWN".yellow(),
+            },
+            // how long a running job has been at it, against how long it may run
+            "elapsed" if job.state == JobState::Running => match job.resources.time_limit {
+                Some(limit) => format!("{} / {}", job.elapsed, format_duration(limit)).normal(),
+                None => job.elapsed.normal(),
+            },
+            _ => format_value(&self.plain_value(job), self.kind),
+        }
+    }
+}
+
+/// Formats a raw sacct value according to its kind
+fn format_value(value: &str, kind: FieldKind) -> ColoredString {
+    match kind {
+        FieldKind::Text | FieldKind::Count | FieldKind::Duration => value.normal(),
+        FieldKind::Timestamp => match NaiveDateTime::parse_from_str(value, INPUT_DATE_FORMAT) {
+            Ok(date) => date.format(START_END_FORMAT).to_string().white(),
+            Err(_) => value.yellow(),
+        },
+        FieldKind::Memory => match parse_memory(value) {
+            Some(bytes) => format_memory(bytes).normal(),
+            None => value.normal(),
+        },
+        FieldKind::State => JobState::parse(value).coloured(),
+        FieldKind::Ratio => match value.strip_suffix('%').and_then(|v| v.parse::<f64>().ok()) {
+            Some(percent) => colour_ratio(value, percent / 100.0),
+            None => value.normal(),
+        },
+        FieldKind::TimeUse => match value.strip_suffix('%').and_then(|v| v.parse::<f64>().ok()) {
+            Some(percent) => colour_time_use(value, percent / 100.0),
+            None => value.normal(),
+        },
+        FieldKind::Explanation => value.yellow(),
+    }
+}
+
+/// The default columns: the fields every query needs anyway and why a job failed
+pub(crate) fn default_columns() -> Vec<String> {
+    FORMAT_CMD
+        .iter()
+        .chain(&["reason"])
+        .map(|field| field.to_string())
+        .collect()
+}
+
+/// Fields to request from sacct to fill `columns`: everything needed to build a [`Job`], then
+/// any additional fields
+pub(crate) fn sacct_fields(columns: &[Column]) -> Vec<&str> {
+    let mut fields = FORMAT_CMD.to_vec();
+    fields.extend(RESOURCE_FIELDS);
+    fields.extend(EXIT_FIELDS);
+    for column in columns.iter().filter(|column| !column.is_derived()) {
+        if !fields.contains(&column.name.as_str()) {
+            fields.push(&column.name);
+        }
+    }
+    fields
+}
+
+/// Parses a sacct memory size such as `1234K`, `1.5G` or `4000Mc` into bytes. Values without a
+/// unit are bytes.
+pub(crate) fn parse_memory(value: &str) -> Option<u64> {
+    // older SLURM versions suffix ReqMem with `c` (per CPU) or `n` (per node)
+    let value = value.trim().trim_end_matches(['c', 'n']);
+    let (number, multiplier) = match value.char_indices().last()? {
+        (idx, unit @ ('K' | 'M' | 'G' | 'T' | 'P')) => {
+            let power = "KMGTP".find(unit).expect("unit is one of KMGTP") as u32 + 1;
+            (&value[..idx], 1024_u64.pow(power))
+        }
+        _ => (value, 1),
+    };
+    let number: f64 = number.parse().ok()?;
+    (number >= 0.0).then(|| (number * multiplier as f64).round() as u64)
+}
+
+/// Formats bytes with binary units, e.g. `1.5G`
+pub(crate) fn format_memory(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return format!("{bytes}B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value >= 100.0 {
+        format!("{value:.0}{}", UNITS[unit])
+    } else {
+        format!("{value:.1}{}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_and_unknown_columns() {
+        let maxrss = Column::new("MaxRSS");
+        assert_eq!(maxrss.name, "maxrss");
+        assert_eq!(maxrss.header, "MaxRSS");
+        assert_eq!(maxrss.kind, FieldKind::Memory);
+
+        let custom = Column::new("AdminComment");
+        assert_eq!(custom.name, "admincomment");
+        assert_eq!(custom.header, "admincomment");
+        assert_eq!(custom.kind, FieldKind::Text);
+    }
+
+    #[test]
+    fn sacct_fields_always_include_core_fields() {
+        let columns: Vec<_> = ["jobid", "partition", "State", "maxrss", "cpueff", "reason"]
+            .into_iter()
+            .map(Column::new)
+            .collect();
+        let fields = sacct_fields(&columns);
+        let core = FORMAT_CMD.len() + RESOURCE_FIELDS.len() + EXIT_FIELDS.len();
+        assert_eq!(&fields[..FORMAT_CMD.len()], FORMAT_CMD);
+        assert_eq!(
+            &fields[FORMAT_CMD.len()..core - EXIT_FIELDS.len()],
+            RESOURCE_FIELDS
+        );
+        // efficiency and explanations are computed, not queried
+        assert_eq!(&fields[core..], ["partition"]);
+    }
+
+    macro_rules! parse_memory_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (input, expected) = $value;
+                assert_eq!(expected, parse_memory(input));
+            }
+    )*
+        }
+    }
+    parse_memory_tests! {
+        parse_memory0: ("1234K", Some(1234 * 1024)),
+        parse_memory1: ("1.5G", Some(1536 * 1024 * 1024)),
+        parse_memory2: ("4000Mc", Some(4000 * 1024 * 1024)),
+        parse_memory3: ("16Gn", Some(16 * 1024 * 1024 * 1024)),
+        parse_memory4: ("0", Some(0)),
+        parse_memory5: ("", None),
+        parse_memory6: ("lots", None),
+    }
+
+    #[test]
+    fn memory_formatting() {
+        assert_eq!(format_memory(512), "512B");
+        assert_eq!(format_memory(1234 * 1024), "1.2M");
+        assert_eq!(format_memory(1536 * 1024 * 1024), "1.5G");
+        assert_eq!(format_memory(300 * 1024 * 1024), "300M");
+    }
+}
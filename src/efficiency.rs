@@ -0,0 +1,215 @@
+use colored::{ColoredString, Colorize};
+use serde::{Deserialize, Serialize};
+
+use crate::columns::parse_memory;
+use crate::{Job, SacctRecord, parse_duration};
+
+/// sacct fields behind [`Resources`], always requested alongside `FORMAT_CMD`
+pub(crate) const RESOURCE_FIELDS: [&str; 4] = ["totalcpu", "maxrss", "reqmem", "timelimit"];
+
+/// Ratios at or above this are shown in green
+const GOOD_EFFICIENCY: f64 = 0.75;
+/// Ratios below this are shown in red, anything in between in yellow
+const POOR_EFFICIENCY: f64 = 0.25;
+/// Time-limit use at or above this is shown in yellow, as the job came close to a TIMEOUT
+const NEAR_TIME_LIMIT: f64 = 0.9;
+
+/// What a job asked for and what it used, as far as sacct knows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Resources {
+    /// CPU time used by all steps, in seconds
+    pub(crate) total_cpu: Option<u64>,
+    /// Peak memory use of the largest step (usually the batch step), in bytes
+    pub(crate) max_rss: Option<u64>,
+    /// Memory requested for the whole job, in bytes
+    pub(crate) req_mem: Option<u64>,
+    /// Time limit, in seconds
+    pub(crate) time_limit: Option<u64>,
+}
+
+impl Resources {
+    /// Reads the [`RESOURCE_FIELDS`] of a job's own record. Missing or unparsable values (e.g. an
+    /// `UNLIMITED` time limit) are left unknown.
+    pub(crate) fn parse(record: &SacctRecord, alloccpus: usize) -> Self {
+        Resources {
+            total_cpu: record.find("totalcpu").and_then(parse_duration),
+            max_rss: record.find("maxrss").and_then(parse_memory),
+            req_mem: record
+                .find("reqmem")
+                .and_then(|value| parse_req_mem(value, alloccpus)),
+            time_limit: record.find("timelimit").and_then(parse_duration),
+        }
+    }
+
+    /// Takes what only a step of the job knows about: sacct reports MaxRSS per step, so the job's
+    /// peak is the largest of its steps
+    pub(crate) fn merge_step(&mut self, step: &SacctRecord) {
+        if let Some(max_rss) = step.find("maxrss").and_then(parse_memory) {
+            self.max_rss = Some(self.max_rss.map_or(max_rss, |rss| rss.max(max_rss)));
+        }
+    }
+
+    pub(crate) fn is_unknown(&self) -> bool {
+        *self == Resources::default()
+    }
+}
+
+/// Parses ReqMem into bytes for the whole job. Older SLURM versions report memory per CPU with a
+/// `c` suffix; per-node requests (`n`) are taken as is since jobs rarely span nodes.
+fn parse_req_mem(value: &str, alloccpus: usize) -> Option<u64> {
+    let bytes = parse_memory(value)?;
+    if value.trim().ends_with('c') {
+        Some(bytes * alloccpus as u64)
+    } else {
+        Some(bytes)
+    }
+}
+
+/// How well a finished job used what it asked for, like `seff` reports it. Every ratio is
+/// unknown when the data behind it is missing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Efficiency {
+    /// TotalCPU over Elapsed × AllocCPUS
+    pub(crate) cpu: Option<f64>,
+    /// MaxRSS over ReqMem
+    pub(crate) memory: Option<f64>,
+    /// Elapsed over Timelimit
+    pub(crate) time: Option<f64>,
+}
+
+impl Efficiency {
+    pub(crate) fn of(job: &Job) -> Self {
+        let ratio = |used: Option<u64>, available: Option<u64>| match (used, available) {
+            (Some(used), Some(available)) if available > 0 => Some(used as f64 / available as f64),
+            _ => None,
+        };
+        let elapsed = job.elapsed_seconds();
+        let resources = &job.resources;
+        Efficiency {
+            cpu: ratio(
+                resources.total_cpu,
+                elapsed.map(|elapsed| elapsed * job.alloccpus as u64),
+            ),
+            memory: ratio(resources.max_rss, resources.req_mem),
+            time: ratio(elapsed, resources.time_limit),
+        }
+    }
+}
+
+/// Formats a ratio as a percentage, e.g. `45.2%`
+pub(crate) fn format_ratio(ratio: f64) -> String {
+    format!("{:.1}%", ratio * 100.0)
+}
+
+/// Colours a formatted ratio by how well the resource was used
+pub(crate) fn colour_ratio(value: &str, ratio: f64) -> ColoredString {
+    if ratio >= GOOD_EFFICIENCY {
+        value.green()
+    } else if ratio >= POOR_EFFICIENCY {
+        value.yellow()
+    } else {
+        value.red()
+    }
+}
+
+/// Colours a formatted share of the time limit. Finishing well within the limit is fine, so
+/// only coming close to it stands out.
+pub(crate) fn colour_time_use(value: &str, ratio: f64) -> ColoredString {
+    if ratio >= NEAR_TIME_LIMIT {
+        value.yellow()
+    } else {
+        value.normal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job_state::JobState;
+
+    fn job(alloccpus: usize, elapsed: &str, resources: Resources) -> Job {
+        Job {
+            jobid_base: 1,
+            array_index: None,
+            jobname: "job".to_string(),
+            alloccpus,
+            elapsed: elapsed.to_string(),
+            start: None,
+            end: None,
+            state: JobState::Completed,
+            resources,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resources_are_read_by_field_name() {
+        let names = [
+            "jobid",
+            "alloccpus",
+            "totalcpu",
+            "maxrss",
+            "reqmem",
+            "timelimit",
+        ];
+        let record = SacctRecord::parse(&names, "1|4|01:30.500||2000Mc|1-00:00:00").unwrap();
+        let resources = Resources::parse(&record, 4);
+        assert_eq!(
+            resources,
+            Resources {
+                total_cpu: Some(90),
+                max_rss: None,
+                req_mem: Some(4 * 2000 * 1024 * 1024),
+                time_limit: Some(24 * 3600),
+            }
+        );
+
+        let record = SacctRecord::parse(&["jobid", "timelimit"], "1|UNLIMITED").unwrap();
+        assert!(Resources::parse(&record, 1).is_unknown());
+    }
+
+    #[test]
+    fn steps_contribute_their_peak_memory() {
+        let mut resources = Resources::default();
+        for line in ["1.batch|1024K", "1.extern|0", "1.0|2048K", "1.1|"] {
+            let record = SacctRecord::parse(&["jobid", "maxrss"], line).unwrap();
+            resources.merge_step(&record);
+        }
+        assert_eq!(resources.max_rss, Some(2048 * 1024));
+    }
+
+    #[test]
+    fn efficiency_ratios() {
+        let resources = Resources {
+            total_cpu: Some(60),
+            max_rss: Some(1024),
+            req_mem: Some(4096),
+            time_limit: Some(600),
+        };
+        let efficiency = Efficiency::of(&job(2, "00:01:00", resources));
+        assert_eq!(efficiency.cpu, Some(0.5));
+        assert_eq!(efficiency.memory, Some(0.25));
+        assert_eq!(efficiency.time, Some(0.1));
+        assert_eq!(format_ratio(efficiency.time.unwrap()), "10.0%");
+
+        // jobs that never ran have nothing to compare against
+        let efficiency = Efficiency::of(&job(2, "00:00:00", Resources::default()));
+        assert_eq!(efficiency.cpu, None);
+        assert_eq!(efficiency.memory, None);
+        assert_eq!(efficiency.time, None);
+    }
+
+    #[test]
+    fn only_time_use_close_to_the_limit_stands_out() {
+        assert_eq!(colour_time_use("10.0%", 0.1).fgcolor, None);
+        assert_eq!(colour_time_use("50.0%", 0.5).fgcolor, None);
+        assert_eq!(
+            colour_time_use("95.0%", 0.95).fgcolor,
+            Some(colored::Color::Yellow)
+        );
+        assert_eq!(
+            colour_ratio("10.0%", 0.1).fgcolor,
+            Some(colored::Color::Red)
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+
+use crate::SacctRecord;
+use crate::job_state::JobState;
+
+/// sacct fields behind [`ExitCode`], always requested alongside `FORMAT_CMD`
+pub(crate) const EXIT_FIELDS: [&str; 2] = ["exitcode", "derivedexitcode"];
+
+/// Exit status a shell reports for a child killed by `signal`
+const SIGNAL_STATUS_OFFSET: u8 = 128;
+/// Highest signal number on Linux; statuses above `128 + SIGRTMAX` are plain exit statuses
+const SIGRTMAX: u8 = 64;
+
+/// How a job or step ended, as reported by sacct's `<status>:<signal>` exit codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ExitCode {
+    /// Exit status of the process, 0 on success
+    pub(crate) status: u8,
+    /// Signal that terminated the process, 0 if none did
+    pub(crate) signal: u8,
+}
+
+impl ExitCode {
+    /// Parses sacct's `<status>:<signal>` notation, e.g. `1:0` or `0:9`
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (status, signal) = value.trim().split_once(':')?;
+        Some(ExitCode {
+            status: status.parse().ok()?,
+            signal: signal.parse().ok()?,
+        })
+    }
+
+    pub(crate) fn is_success(&self) -> bool {
+        self.status == 0 && self.signal == 0
+    }
+
+    /// The signal that ended the process, also when a shell passed it on as `128 + signal`
+    fn effective_signal(&self) -> Option<u8> {
+        if self.signal != 0 {
+            Some(self.signal)
+        } else if (SIGNAL_STATUS_OFFSET + 1..=SIGNAL_STATUS_OFFSET + SIGRTMAX)
+            .contains(&self.status)
+        {
+            Some(self.status - SIGNAL_STATUS_OFFSET)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.status, self.signal)
+    }
+}
+
+/// The exit codes sacct reports for a job: its batch script's, and the highest of any of its
+/// steps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ExitCodes {
+    pub(crate) exit_code: Option<ExitCode>,
+    pub(crate) derived_exit_code: Option<ExitCode>,
+}
+
+impl ExitCodes {
+    pub(crate) fn parse(record: &SacctRecord) -> Self {
+        ExitCodes {
+            exit_code: record.find("exitcode").and_then(ExitCode::parse),
+            derived_exit_code: record.find("derivedexitcode").and_then(ExitCode::parse),
+        }
+    }
+
+    pub(crate) fn is_unknown(&self) -> bool {
+        *self == ExitCodes::default()
+    }
+
+    /// The exit code that tells why the job failed: the job's own, unless that one was clean
+    /// and one of its steps wasn't
+    fn relevant(&self) -> Option<ExitCode> {
+        match (self.exit_code, self.derived_exit_code) {
+            (Some(code), Some(derived)) if code.is_success() => Some(derived),
+            (Some(code), _) => Some(code),
+            (None, derived) => derived,
+        }
+    }
+}
+
+fn signal_name(signal: u8) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        4 => "SIGILL",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        15 => "SIGTERM",
+        24 => "SIGXCPU",
+        25 => "SIGXFSZ",
+        _ => return None,
+    })
+}
+
+/// A short human-readable guess at why a job ended the way it did, or `None` if it succeeded
+pub(crate) fn explain(state: &JobState, exit_codes: &ExitCodes) -> Option<String> {
+    match state {
+        JobState::OutOfMemory => return Some("out of memory".to_string()),
+        JobState::NodeFail => return Some("node failure, not caused by the job".to_string()),
+        JobState::Timeout => return Some("reached its time limit".to_string()),
+        JobState::Preempted => return Some("preempted by another job".to_string()),
+        JobState::BootFail => return Some("node failed to boot".to_string()),
+        _ => {}
+    }
+
+    let code = exit_codes.relevant().filter(|code| !code.is_success())?;
+    let explanation = match (code.status, code.effective_signal()) {
+        (_, Some(9)) => "killed (SIGKILL), likely out of memory or over a resource limit",
+        (_, Some(11)) => "segmentation fault (SIGSEGV)",
+        (_, Some(6)) => "aborted (SIGABRT)",
+        (_, Some(15)) => "terminated (SIGTERM)",
+        (127, None) => "command not found",
+        (126, None) => "command not executable",
+        (_, Some(signal)) => {
+            return Some(match signal_name(signal) {
+                Some(name) => format!("killed by {name}"),
+                None => format!("killed by signal {signal}"),
+            });
+        }
+        (status, None) => return Some(format!("exited with status {status}")),
+    };
+    Some(explanation.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_read_by_field_name() {
+        let names = ["jobid", "exitcode", "derivedexitcode"];
+        let record = SacctRecord::parse(&names, "1|0:9|139:0").unwrap();
+        let exit_codes = ExitCodes::parse(&record);
+        assert_eq!(
+            exit_codes.exit_code,
+            Some(ExitCode {
+                status: 0,
+                signal: 9
+            })
+        );
+        assert_eq!(exit_codes.derived_exit_code.unwrap().to_string(), "139:0");
+
+        let record = SacctRecord::parse(&["jobid", "exitcode"], "1|").unwrap();
+        assert!(ExitCodes::parse(&record).is_unknown());
+    }
+
+    macro_rules! explain_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (state, exit_code, derived_exit_code, expected) = $value;
+                let exit_codes = ExitCodes {
+                    exit_code: ExitCode::parse(exit_code),
+                    derived_exit_code: ExitCode::parse(derived_exit_code),
+                };
+                assert_eq!(expected, explain(&JobState::parse(state), &exit_codes).as_deref());
+            }
+    )*
+        }
+    }
+    explain_tests! {
+        explain0: ("COMPLETED", "0:0", "0:0", None),
+        explain1: ("FAILED", "1:0", "0:0", Some("exited with status 1")),
+        explain2: ("FAILED", "137:0", "", Some("killed (SIGKILL), likely out of memory or over a resource limit")),
+        explain3: ("CANCELLED by 0", "0:9", "0:0", Some("killed (SIGKILL), likely out of memory or over a resource limit")),
+        explain4: ("FAILED", "139:0", "0:0", Some("segmentation fault (SIGSEGV)")),
+        explain5: ("FAILED", "0:0", "0:11", Some("segmentation fault (SIGSEGV)")),
+        explain6: ("FAILED", "127:0", "0:0", Some("command not found")),
+        explain7: ("OUT_OF_MEMORY", "0:125", "0:0", Some("out of memory")),
+        explain8: ("NODE_FAIL", "1:0", "0:0", Some("node failure, not caused by the job")),
+        explain9: ("TIMEOUT", "0:0", "0:15", Some("reached its time limit")),
+        explain10: ("FAILED", "0:7", "", Some("killed by SIGBUS")),
+        explain11: ("FAILED", "0:40", "", Some("killed by signal 40")),
+        explain12: ("FAILED", "", "", None),
+        explain13: ("FAILED", "255:0", "0:0", Some("exited with status 255")),
+        explain14: ("FAILED", "192:0", "0:0", Some("killed by signal 64")),
+    }
+}
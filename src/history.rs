@@ -0,0 +1,630 @@
+//! Local record of every job `jobs_done` has seen.
+//!
+//! The history is a [JSON Lines](https://jsonlines.org/) file: one [`HistoryRecord`] object per
+//! line, e.g.
+//!
+//! ```text
+//! {"jobid_base":56938944,"array_index":2,"jobname":"ArrayJob","alloccpus":2,"elapsed":"01:00:00","start":"2023-04-22T16:15:05","end":"2023-04-22T17:15:05","state":"TIMEOUT","acknowledged":true}
+//! ```
+//!
+//! Timestamps are ISO 8601 in the cluster's local time, missing values are `null`. The
+//! `resources` a job requested and used and its `exit_codes` are kept when known, as are
+//! additional sacct fields requested with `--columns`, in an `extra` object. Components of a
+//! heterogeneous job have a `het_offset` as well. Each job, identified by (`jobid_base`,
+//! `array_index`, `het_offset`), appears at most once. Records are sorted by that key and the
+//! whole file is rewritten whenever a job is added or any of its details change.
+//!
+//! `acknowledged` is set once a job has been shown in unseen mode (or acknowledged explicitly)
+//! and cleared again if its state changes afterwards. It may be missing, meaning `false`.
+//!
+//! `hooks` maps every `--hook` that was run for the job to its [`HookOutcome`], so no hook runs
+//! twice for the same job. Deliveries to a `--webhook` are kept there too, as `webhook=<hash>`
+//! with a hash of the URL, which is a secret. Unlike `acknowledged`, it survives state changes,
+//! as does `resubmitted_as`: the ID of the job or array task that `resubmit` or
+//! `resubmit-array` submitted to repeat this one.
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::efficiency::Resources;
+use crate::exit_code::ExitCodes;
+use crate::job_state::JobState;
+use crate::{Job, JobKey, ParsedJobId, check_job, state};
+
+/// A job as stored in the history file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HistoryRecord {
+    jobid_base: usize,
+    array_index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    het_offset: Option<usize>,
+    jobname: String,
+    alloccpus: usize,
+    elapsed: String,
+    start: Option<NaiveDateTime>,
+    end: Option<NaiveDateTime>,
+    state: String,
+    #[serde(default, skip_serializing_if = "Resources::is_unknown")]
+    resources: Resources,
+    #[serde(default, skip_serializing_if = "ExitCodes::is_unknown")]
+    exit_codes: ExitCodes,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    extra: BTreeMap<String, String>,
+    #[serde(default)]
+    acknowledged: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    hooks: BTreeMap<String, HookOutcome>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resubmitted_as: Option<String>,
+}
+
+/// What came of running a hook command for a job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct HookOutcome {
+    pub(crate) started: NaiveDateTime,
+    /// Exit code of the command. Missing while it runs, or if it was killed by a signal or
+    /// couldn't be started at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+impl From<&Job> for HistoryRecord {
+    fn from(job: &Job) -> Self {
+        HistoryRecord {
+            jobid_base: job.jobid_base,
+            array_index: job.array_index,
+            het_offset: job.het_offset,
+            jobname: job.jobname.clone(),
+            alloccpus: job.alloccpus,
+            elapsed: job.elapsed.clone(),
+            start: job.start,
+            end: job.end,
+            state: job.state.to_string(),
+            resources: job.resources,
+            exit_codes: job.exit_codes,
+            extra: job.extra.clone(),
+            acknowledged: false,
+            hooks: BTreeMap::new(),
+            resubmitted_as: None,
+        }
+    }
+}
+
+impl From<HistoryRecord> for Job {
+    fn from(record: HistoryRecord) -> Self {
+        Job {
+            jobid_base: record.jobid_base,
+            array_index: record.array_index,
+            jobname: record.jobname,
+            alloccpus: record.alloccpus,
+            elapsed: record.elapsed,
+            start: record.start,
+            end: record.end,
+            state: JobState::parse(&record.state),
+            resources: record.resources,
+            exit_codes: record.exit_codes,
+            extra: record.extra,
+            het_offset: record.het_offset,
+            pending_tasks: None,
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl HistoryRecord {
+    fn key(&self) -> JobKey {
+        (self.jobid_base, self.array_index, self.het_offset)
+    }
+
+    /// Parses a line of the old append-only `log_file`
+    /// (`jobid;jobname;alloccpus;elapsed;Some(start);Some(end);state`).
+    fn from_legacy_line(line: &str) -> Option<Self> {
+        let parse_date = |value: &str| match value {
+            "None" => Some(None),
+            _ => value
+                .strip_prefix("Some(")?
+                .strip_suffix(')')
+                .and_then(|date| date.parse::<NaiveDateTime>().ok())
+                .map(Some),
+        };
+
+        // the job name may itself contain ';', so peel the fixed fields off both ends
+        let mut fields = line.rsplitn(6, ';');
+        let state = fields.next()?;
+        let end = parse_date(fields.next()?)?;
+        let start = parse_date(fields.next()?)?;
+        let elapsed = fields.next()?;
+        let alloccpus = fields.next()?.parse().ok()?;
+        let (jobid, jobname) = fields.next()?.split_once(';')?;
+        let (jobid_base, array_index) = match check_job(jobid) {
+            ParsedJobId::Singular(id) => (id, None),
+            ParsedJobId::Array { base, index } => (base, Some(index)),
+            _ => return None,
+        };
+
+        Some(HistoryRecord {
+            jobid_base,
+            array_index,
+            het_offset: None,
+            jobname: jobname.to_string(),
+            alloccpus,
+            elapsed: elapsed.to_string(),
+            start,
+            end,
+            state: state.to_string(),
+            resources: Resources::default(),
+            exit_codes: ExitCodes::default(),
+            extra: BTreeMap::new(),
+            acknowledged: false,
+            hooks: BTreeMap::new(),
+            resubmitted_as: None,
+        })
+    }
+}
+
+/// Matches `text` against a shell-style glob where `*` matches any run of characters and `?`
+/// matches exactly one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<_> = pattern.chars().collect();
+    let text: Vec<_> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it was tried against, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Selects which stored jobs to show
+#[derive(Debug, Default)]
+pub(crate) struct HistoryFilter {
+    /// Glob the job name has to match
+    pub(crate) name: Option<String>,
+    /// States to keep, by name or short code. Empty keeps every state.
+    pub(crate) states: Vec<String>,
+    /// Only keep jobs that ended (or, if they never ended, started) at or after this time
+    pub(crate) since: Option<NaiveDateTime>,
+}
+
+impl HistoryFilter {
+    pub(crate) fn matches(&self, job: &Job) -> bool {
+        let name_matches = self
+            .name
+            .as_deref()
+            .is_none_or(|pattern| glob_match(pattern, &job.jobname));
+        let state_matches =
+            self.states.is_empty() || self.states.iter().any(|state| job.state.matches(state));
+        let recent_enough = self
+            .since
+            .is_none_or(|since| job.end.or(job.start).is_some_and(|date| date >= since));
+        name_matches && state_matches && recent_enough
+    }
+}
+
+/// In-memory view of the history file
+#[derive(Debug)]
+pub(crate) struct HistoryStore {
+    path: PathBuf,
+    records: BTreeMap<JobKey, HistoryRecord>,
+    dirty: bool,
+}
+
+impl HistoryStore {
+    /// Loads the history at `path`. A missing file is an empty history. Malformed records, e.g.
+    /// from a truncated write or a hand edit, are skipped with a warning and dropped from the
+    /// file the next time it's saved.
+    pub(crate) fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut records = BTreeMap::new();
+        let mut dirty = false;
+        if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("unable to read history file {}", path.display()))?;
+            for (line_idx, line) in contents.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<HistoryRecord>(line) {
+                    Ok(record) => {
+                        records.insert(record.key(), record);
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "{} skipping malformed record on line {} of {} ({err})",
+                            "warning:".yellow().bold(),
+                            line_idx + 1,
+                            path.display()
+                        );
+                        dirty = true;
+                    }
+                }
+            }
+        }
+
+        Ok(HistoryStore {
+            path,
+            records,
+            dirty,
+        })
+    }
+
+    /// Adds the jobs of an old `log_file` that aren't in the history yet. Unreadable lines are
+    /// skipped. Returns how many jobs were imported.
+    pub(crate) fn import_legacy_log(&mut self, log_file: &Path) -> Result<usize> {
+        let contents = fs::read_to_string(log_file)
+            .with_context(|| format!("unable to read legacy log {}", log_file.display()))?;
+        let mut imported = 0;
+        for record in contents.lines().filter_map(HistoryRecord::from_legacy_line) {
+            // later lines of the legacy log are newer, so they win
+            if self.records.insert(record.key(), record).is_none() {
+                imported += 1;
+            }
+            self.dirty = true;
+        }
+        Ok(imported)
+    }
+
+    /// Inserts `job`, or updates the stored record with whatever changed since, e.g. the end
+    /// and exit codes of a job that was requeued. Resources, exit codes and extra fields that
+    /// weren't queried this time are kept, as are hook outcomes and resubmissions. The job stays
+    /// acknowledged only if its state didn't change.
+    pub(crate) fn upsert(&mut self, job: &Job) {
+        let mut record = HistoryRecord::from(job);
+        let stored = self.records.get(&record.key());
+        if let Some(stored) = stored {
+            if record.resources.is_unknown() {
+                record.resources = stored.resources;
+            }
+            if record.exit_codes.is_unknown() {
+                record.exit_codes = stored.exit_codes;
+            }
+            let mut extra = stored.extra.clone();
+            extra.append(&mut record.extra);
+            record.extra = extra;
+            record.acknowledged = stored.acknowledged && stored.state == record.state;
+            record.hooks = stored.hooks.clone();
+            record.resubmitted_as = stored.resubmitted_as.clone();
+        }
+        if stored != Some(&record) {
+            self.records.insert(record.key(), record);
+            self.dirty = true;
+        }
+    }
+
+    /// Whether `job` was acknowledged in its current state
+    pub(crate) fn is_acknowledged(&self, job: &Job) -> bool {
+        self.records
+            .get(&job.key())
+            .is_some_and(|stored| stored.acknowledged && stored.state == job.state.to_string())
+    }
+
+    /// Records `job` and marks it as acknowledged, so unseen mode won't show it again
+    pub(crate) fn acknowledge(&mut self, job: &Job) {
+        self.upsert(job);
+        let record = self
+            .records
+            .get_mut(&job.key())
+            .expect("job was just upserted");
+        if !record.acknowledged {
+            record.acknowledged = true;
+            self.dirty = true;
+        }
+    }
+
+    /// The outcome of the `hook` run for `job`, if it was run before
+    pub(crate) fn hook_outcome(&self, job: &Job, hook: &str) -> Option<&HookOutcome> {
+        self.records.get(&job.key())?.hooks.get(hook)
+    }
+
+    /// Records `job` and what came of running `hook` for it
+    pub(crate) fn record_hook(&mut self, job: &Job, hook: &str, outcome: HookOutcome) {
+        self.upsert(job);
+        let record = self
+            .records
+            .get_mut(&job.key())
+            .expect("job was just upserted");
+        record.hooks.insert(hook.to_string(), outcome);
+        self.dirty = true;
+    }
+
+    /// The job or array task `job` was last resubmitted as, if it was
+    pub(crate) fn resubmitted_as(&self, job: &Job) -> Option<&str> {
+        self.records.get(&job.key())?.resubmitted_as.as_deref()
+    }
+
+    /// Records `job` and that it was resubmitted as `jobid`
+    pub(crate) fn record_resubmission(&mut self, job: &Job, jobid: &str) {
+        self.upsert(job);
+        let record = self
+            .records
+            .get_mut(&job.key())
+            .expect("job was just upserted");
+        record.resubmitted_as = Some(jobid.to_string());
+        self.dirty = true;
+    }
+
+    /// Every stored job, ordered by job ID and array index
+    pub(crate) fn jobs(&self) -> impl Iterator<Item = Job> + '_ {
+        self.records.values().cloned().map(Job::from)
+    }
+
+    /// Writes the history back to disk if anything changed. The file is replaced atomically so
+    /// an interrupted run never leaves a truncated history behind.
+    pub(crate) fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut contents = Vec::new();
+        for record in self.records.values() {
+            serde_json::to_writer(&mut contents, record)
+                .context("unable to serialize history record")?;
+            contents.push(b'\n');
+        }
+        state::write_atomically(&self.path, &contents)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::INPUT_DATE_FORMAT;
+    use crate::exit_code::ExitCode;
+
+    fn get(store: &HistoryStore, jobid_base: usize, array_index: Option<usize>) -> Job {
+        store
+            .jobs()
+            .find(|job| job.jobid_base == jobid_base && job.array_index == array_index)
+            .unwrap()
+    }
+
+    fn job(jobid_base: usize, array_index: Option<usize>, state: &str) -> Job {
+        Job {
+            jobid_base,
+            array_index,
+            jobname: "relax; slab".to_string(),
+            alloccpus: 4,
+            elapsed: "00:05:00".to_string(),
+            start: Some(
+                NaiveDateTime::parse_from_str("2023-04-22T16:15:05", INPUT_DATE_FORMAT).unwrap(),
+            ),
+            end: None,
+            state: JobState::parse(state),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn upsert_deduplicates_and_tracks_state_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut store = HistoryStore::open(&path).unwrap();
+        store.upsert(&job(1, None, "RUNNING"));
+        store.upsert(&job(2, Some(3), "COMPLETED"));
+        store.save().unwrap();
+
+        let mut store = HistoryStore::open(&path).unwrap();
+        store.upsert(&job(2, Some(3), "COMPLETED"));
+        assert!(!store.dirty);
+        store.upsert(&job(1, None, "FAILED"));
+        store.save().unwrap();
+
+        let store = HistoryStore::open(&path).unwrap();
+        let jobs: Vec<_> = store.jobs().collect();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].state, JobState::Failed);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn upsert_refreshes_details_without_a_state_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut store = HistoryStore::open(&path).unwrap();
+        let mut failed = job(1, None, "FAILED");
+        failed.exit_codes.exit_code = ExitCode::parse("1:0");
+        failed
+            .extra
+            .insert("partition".to_string(), "short".to_string());
+        store.acknowledge(&failed);
+        store.save().unwrap();
+
+        // requeued and failed again, later, this time queried without exit codes or extra fields
+        let mut store = HistoryStore::open(&path).unwrap();
+        let mut again = job(1, None, "FAILED");
+        again.elapsed = "00:07:00".to_string();
+        again.end = failed.start;
+        store.upsert(&again);
+        assert!(store.dirty);
+        assert!(store.is_acknowledged(&again));
+
+        let stored = get(&store, 1, None);
+        assert_eq!(stored.elapsed, "00:07:00");
+        assert_eq!(stored.end, failed.start);
+        assert_eq!(stored.exit_codes, failed.exit_codes);
+        assert_eq!(stored.extra, failed.extra);
+    }
+
+    #[test]
+    fn malformed_records_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut store = HistoryStore::open(&path).unwrap();
+        store.upsert(&job(1, None, "COMPLETED"));
+        store.upsert(&job(2, None, "FAILED"));
+        store.save().unwrap();
+
+        // a hand edit and a write cut short
+        let contents = fs::read_to_string(&path).unwrap();
+        let (first, second) = contents.trim_end().split_once('\n').unwrap();
+        let damaged = format!("{first}\nnot json\n{}\n", &second[..second.len() / 2]);
+        fs::write(&path, damaged).unwrap();
+
+        let mut store = HistoryStore::open(&path).unwrap();
+        assert_eq!(store.jobs().count(), 1);
+        store.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{first}\n"));
+    }
+
+    #[test]
+    fn hook_outcomes_survive_state_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut store = HistoryStore::open(&path).unwrap();
+        let failed = job(1, None, "FAILED");
+        let outcome = HookOutcome {
+            started: failed.start.unwrap(),
+            exit_code: Some(0),
+            error: None,
+        };
+        store.record_hook(&failed, "ANY=true", outcome.clone());
+        store.save().unwrap();
+
+        let mut store = HistoryStore::open(&path).unwrap();
+        assert_eq!(store.hook_outcome(&failed, "ANY=true"), Some(&outcome));
+        assert_eq!(store.hook_outcome(&failed, "FAILED=true"), None);
+        let completed = job(1, None, "COMPLETED");
+        store.upsert(&completed);
+        assert_eq!(store.hook_outcome(&completed, "ANY=true"), Some(&outcome));
+    }
+
+    #[test]
+    fn resubmissions_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut store = HistoryStore::open(&path).unwrap();
+        let failed = job(1, Some(3), "FAILED");
+        assert_eq!(store.resubmitted_as(&failed), None);
+        store.record_resubmission(&failed, "7_3");
+        store.save().unwrap();
+
+        let mut store = HistoryStore::open(&path).unwrap();
+        assert_eq!(store.resubmitted_as(&failed), Some("7_3"));
+        store.upsert(&job(1, Some(3), "COMPLETED"));
+        assert_eq!(store.resubmitted_as(&failed), Some("7_3"));
+    }
+
+    #[test]
+    fn acknowledgement_is_reset_by_state_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut store = HistoryStore::open(&path).unwrap();
+        let failed = job(1, Some(2), "FAILED");
+        assert!(!store.is_acknowledged(&failed));
+
+        store.acknowledge(&failed);
+        store.save().unwrap();
+        let mut store = HistoryStore::open(&path).unwrap();
+        assert!(store.is_acknowledged(&failed));
+        assert!(!store.is_acknowledged(&job(1, None, "FAILED")));
+
+        // e.g. requeued and finished again
+        let completed = job(1, Some(2), "COMPLETED");
+        assert!(!store.is_acknowledged(&completed));
+        store.upsert(&completed);
+        assert!(!store.is_acknowledged(&completed));
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let original = job(56938944, Some(2), "TIMEOUT");
+        let mut store = HistoryStore::open(&path).unwrap();
+        store.upsert(&original);
+        store.save().unwrap();
+
+        let stored = get(&HistoryStore::open(&path).unwrap(), 56938944, Some(2));
+        assert_eq!(HistoryRecord::from(&stored), HistoryRecord::from(&original));
+    }
+
+    #[test]
+    fn imports_legacy_log_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let log_file = path.with_file_name("log_file");
+        fs::write(
+            &log_file,
+            "\
+39139726;1e-2;84;00:08:58;Some(2023-04-22T16:15:05);Some(2023-04-22T16:24:03);COMPLETED
+56938944_3;odd;name;2;00:01:00;Some(2023-04-22T16:15:05);None;FAILED
+garbage line
+39139726;1e-2;84;00:08:58;Some(2023-04-22T16:15:05);Some(2023-04-22T16:24:03);COMPLETED
+",
+        )
+        .unwrap();
+
+        let mut store = HistoryStore::open(&path).unwrap();
+        assert_eq!(store.import_legacy_log(&log_file).unwrap(), 2);
+        let array = get(&store, 56938944, Some(3));
+        assert_eq!(array.jobname, "odd;name");
+        assert_eq!(array.end, None);
+        assert_eq!(array.state, JobState::Failed);
+        assert_eq!(get(&store, 39139726, None).alloccpus, 84);
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("opt_*", "opt_water"));
+        assert!(glob_match("opt_*", "opt_"));
+        assert!(!glob_match("opt_*", "sp_water"));
+        assert!(glob_match("*water*", "opt_water_dimer"));
+        assert!(glob_match("opt_?", "opt_1"));
+        assert!(!glob_match("opt_?", "opt_12"));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+
+    #[test]
+    fn filter_by_name_state_and_date() {
+        let since =
+            NaiveDateTime::parse_from_str("2023-04-22T00:00:00", INPUT_DATE_FORMAT).unwrap();
+        let filter = HistoryFilter {
+            name: Some("relax*".to_string()),
+            states: vec!["FAILED".to_string()],
+            since: Some(since),
+        };
+        assert!(filter.matches(&job(1, None, "FAILED")));
+        assert!(!filter.matches(&job(1, None, "COMPLETED")));
+
+        let mut renamed = job(1, None, "FAILED");
+        renamed.jobname = "opt_water".to_string();
+        assert!(!filter.matches(&renamed));
+
+        let mut old = job(1, None, "FAILED");
+        old.start = Some(since - chrono::Duration::days(1));
+        assert!(!filter.matches(&old));
+
+        assert!(HistoryFilter::default().matches(&old));
+    }
+}
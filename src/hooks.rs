@@ -0,0 +1,283 @@
+use anyhow::{Result, bail};
+use chrono::Local;
+use colored::Colorize;
+use std::fmt;
+use std::process::{Command, Stdio};
+
+use crate::backend::Backend;
+use crate::history::HookOutcome;
+use crate::state::StateDir;
+use crate::{Job, get_workdir, open_history};
+
+/// Stands in for the state of a hook that runs for jobs in any state
+const ANY_STATE: &str = "ANY";
+
+/// A shell command to run once for every finished job, or only for jobs that ended in `state`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Hook {
+    state: Option<String>,
+    command: String,
+}
+
+impl Hook {
+    /// Parses `STATE=COMMAND`, where `STATE` is e.g. `FAILED`, or `ANY` to match every state
+    pub(crate) fn parse(value: &str) -> Result<Self> {
+        let Some((state, command)) = value.split_once('=') else {
+            bail!("expected STATE=COMMAND, e.g. FAILED='notify-send \"$JOBS_DONE_JOBID failed\"'");
+        };
+        let state = state.trim().to_uppercase();
+        if state.is_empty() || command.trim().is_empty() {
+            bail!("expected STATE=COMMAND, got {value:?}");
+        }
+        Ok(Hook {
+            state: (state != ANY_STATE).then_some(state),
+            command: command.to_string(),
+        })
+    }
+
+    fn matches(&self, job: &Job) -> bool {
+        self.state
+            .as_deref()
+            .is_none_or(|state| job.state.matches(state))
+    }
+
+    /// Variables describing `job` to the command
+    fn environment(job: &Job, workdir: &str) -> Vec<(&'static str, String)> {
+        let exit_code = job.exit_codes.exit_code;
+        vec![
+            ("JOBS_DONE_JOBID", job.jobid_display()),
+            ("JOBS_DONE_JOBNAME", job.jobname.clone()),
+            ("JOBS_DONE_STATE", job.state.to_string()),
+            ("JOBS_DONE_WORKDIR", workdir.to_string()),
+            (
+                "JOBS_DONE_EXIT_CODE",
+                exit_code.map_or_else(String::new, |code| code.status.to_string()),
+            ),
+            (
+                "JOBS_DONE_EXIT_SIGNAL",
+                exit_code.map_or_else(String::new, |code| code.signal.to_string()),
+            ),
+        ]
+    }
+
+    /// Runs the command with `sh` and waits for it. Its output goes to stderr, so it can't end
+    /// up in machine-readable output on stdout.
+    fn run(&self, job: &Job, workdir: &str) -> HookOutcome {
+        let started = Local::now().naive_local();
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .envs(Self::environment(job, workdir))
+            .stdin(Stdio::null())
+            .stdout(std::io::stderr())
+            .status();
+        match status {
+            Ok(status) => HookOutcome {
+                started,
+                exit_code: status.code(),
+                error: status.code().is_none().then(|| status.to_string()),
+            },
+            Err(err) => HookOutcome {
+                started,
+                exit_code: None,
+                error: Some(format!("unable to run sh: {err}")),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state.as_deref().unwrap_or(ANY_STATE);
+        write!(f, "{state}={}", self.command)
+    }
+}
+
+/// The (job, hook) pairs among `jobs` that are due and haven't been started before
+fn due<'a>(
+    hooks: &'a [Hook],
+    jobs: &'a [Job],
+    already_run: impl Fn(&Job, &str) -> bool,
+) -> Vec<(&'a Job, &'a Hook)> {
+    jobs.iter()
+        .filter(|job| job.is_finished())
+        .flat_map(|job| hooks.iter().map(move |hook| (job, hook)))
+        .filter(|(job, hook)| hook.matches(job) && !already_run(job, &hook.to_string()))
+        .collect()
+}
+
+/// Runs every hook that matches one of the finished `jobs` and hasn't been run for it before,
+/// in this or any other session. Each run is recorded in the history before it starts, so a
+/// hook interrupted halfway isn't repeated either.
+pub(crate) fn run(
+    backend: &dyn Backend,
+    state_dir: &StateDir,
+    hooks: &[Hook],
+    jobs: &[Job],
+) -> Result<()> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    let claimed = {
+        let _lock = state_dir.lock()?;
+        let mut history = open_history(&state_dir.history_file(), &state_dir.legacy_log_file())?;
+        let claimed = due(hooks, jobs, |job, hook| {
+            history.hook_outcome(job, hook).is_some()
+        });
+        for (job, hook) in &claimed {
+            let outcome = HookOutcome {
+                started: Local::now().naive_local(),
+                exit_code: None,
+                error: None,
+            };
+            history.record_hook(job, &hook.to_string(), outcome);
+        }
+        history.save()?;
+        claimed
+    };
+    if claimed.is_empty() {
+        return Ok(());
+    }
+
+    // the lock isn't held while hooks run, they may take a while
+    let mut outcomes = Vec::new();
+    for (job, hook) in claimed {
+        let jobid = job.jobid_display();
+        let workdir = match job.extra.get("workdir") {
+            Some(workdir) => Ok(workdir.clone()),
+            None => get_workdir(backend, &jobid),
+        };
+        // a hook can't be trusted to work in the wrong directory, so it's failed instead
+        let outcome = match workdir {
+            Ok(workdir) => hook.run(job, &workdir),
+            Err(err) => HookOutcome {
+                started: Local::now().naive_local(),
+                exit_code: None,
+                error: Some(format!("{err:#}")),
+            },
+        };
+        if outcome.exit_code != Some(0) {
+            let reason = match (&outcome.error, outcome.exit_code) {
+                (Some(error), _) => error.clone(),
+                (None, code) => format!("exit code {}", code.unwrap_or_default()),
+            };
+            eprintln!(
+                "{} hook {hook} failed for job {jobid}: {reason}",
+                "warning:".yellow().bold()
+            );
+        }
+        outcomes.push((job, hook, outcome));
+    }
+
+    let _lock = state_dir.lock()?;
+    let mut history = open_history(&state_dir.history_file(), &state_dir.legacy_log_file())?;
+    for (job, hook, outcome) in outcomes {
+        history.record_hook(job, &hook.to_string(), outcome);
+    }
+    history.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Replay, replay_fixtures, replayed_jobs};
+    use crate::job_state::JobState;
+    use std::fs;
+
+    #[test]
+    fn hooks_are_parsed() {
+        let hook = Hook::parse("failed=echo a=b").unwrap();
+        assert_eq!(hook.state.as_deref(), Some("FAILED"));
+        assert_eq!(hook.command, "echo a=b");
+        assert_eq!(hook.to_string(), "FAILED=echo a=b");
+        assert_eq!(Hook::parse("ANY=true").unwrap().state, None);
+        assert!(Hook::parse("true").is_err());
+        assert!(Hook::parse("=true").is_err());
+        assert!(Hook::parse("FAILED=").is_err());
+    }
+
+    #[test]
+    fn due_hooks_match_state_and_skip_earlier_runs() {
+        let jobs = replayed_jobs();
+        let hooks = [
+            Hook::parse("FAILED=a").unwrap(),
+            Hook::parse("ANY=b").unwrap(),
+        ];
+        let due_ids = |already_run: &dyn Fn(&Job, &str) -> bool| -> Vec<String> {
+            due(&hooks, &jobs, already_run)
+                .into_iter()
+                .map(|(job, hook)| format!("{} {}", job.jobid_display(), hook.command))
+                .collect()
+        };
+
+        // the running job gets nothing
+        assert_eq!(
+            due_ids(&|_, _| false),
+            [
+                "56938942 b",
+                "56938943 a",
+                "56938943 b",
+                "56938944_1 b",
+                "56938944_2 b"
+            ]
+        );
+        assert_eq!(
+            due_ids(&|job, hook| job.array_index.is_some() || hook == "ANY=b"),
+            ["56938943 a"]
+        );
+    }
+
+    #[test]
+    fn commands_see_the_job_and_run_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = StateDir::resolve(Some(&dir.path().join("state")), "me").unwrap();
+        let backend = replay_fixtures();
+        let out = dir.path().join("out");
+        let hooks = [Hook::parse(&format!(
+            "FAILED=echo \"$JOBS_DONE_JOBID $JOBS_DONE_JOBNAME $JOBS_DONE_STATE $JOBS_DONE_WORKDIR $JOBS_DONE_EXIT_CODE\" >> {}",
+            out.display()
+        ))
+        .unwrap()];
+        let jobs = replayed_jobs();
+
+        run(&backend, &state_dir, &hooks, &jobs).unwrap();
+        run(&backend, &state_dir, &hooks, &jobs).unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "56938943 relax slab 2x2 FAILED /home/me/slab 139\n"
+        );
+
+        let history =
+            open_history(&state_dir.history_file(), &state_dir.legacy_log_file()).unwrap();
+        let failed = jobs
+            .iter()
+            .find(|job| job.state == JobState::Failed)
+            .unwrap();
+        let outcome = history.hook_outcome(failed, &hooks[0].to_string()).unwrap();
+        assert_eq!(outcome.exit_code, Some(0));
+    }
+
+    #[test]
+    fn hooks_are_not_run_without_a_workdir() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = StateDir::resolve(Some(&dir.path().join("state")), "me").unwrap();
+        let backend = Replay::new(dir.path().join("missing"));
+        let out = dir.path().join("out");
+        let hooks = [Hook::parse(&format!("FAILED=touch {}", out.display())).unwrap()];
+        let jobs = replayed_jobs();
+
+        run(&backend, &state_dir, &hooks, &jobs).unwrap();
+        assert!(!out.exists());
+
+        let history =
+            open_history(&state_dir.history_file(), &state_dir.legacy_log_file()).unwrap();
+        let failed = jobs
+            .iter()
+            .find(|job| job.state == JobState::Failed)
+            .unwrap();
+        let outcome = history.hook_outcome(failed, &hooks[0].to_string()).unwrap();
+        assert_eq!(outcome.exit_code, None);
+        assert!(outcome.error.as_ref().unwrap().contains("replay directory"));
+    }
+}
@@ -0,0 +1,246 @@
+use colored::{ColoredString, Colorize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Mutex;
+
+/// How a job ended, or that it hasn't yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Category {
+    Success,
+    Failure,
+    Cancelled,
+    InProgress,
+}
+
+/// A SLURM job state as reported by sacct
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum JobState {
+    BootFail,
+    /// `CANCELLED by <uid>`, by the user with that ID if sacct says who it was
+    Cancelled {
+        by: Option<u32>,
+    },
+    Completed,
+    Completing,
+    Configuring,
+    Deadline,
+    Failed,
+    NodeFail,
+    OutOfMemory,
+    Pending,
+    Preempted,
+    Requeued,
+    RequeueFed,
+    RequeueHold,
+    Resizing,
+    ResvDelHold,
+    Revoked,
+    Running,
+    Signaling,
+    SpecialExit,
+    StageOut,
+    Stopped,
+    Suspended,
+    Timeout,
+    /// A state this version doesn't know, as reported
+    Other(String),
+}
+
+/// Unknown states that have been warned about, so each is only warned about once
+static WARNED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Every known state with its name in sacct output and the short code squeue shows
+const STATES: [(JobState, &str, &str); 24] = [
+    (JobState::BootFail, "BOOT_FAIL", "BF"),
+    (JobState::Cancelled { by: None }, "CANCELLED", "CA"),
+    (JobState::Completed, "COMPLETED", "CD"),
+    (JobState::Completing, "COMPLETING", "CG"),
+    (JobState::Configuring, "CONFIGURING", "CF"),
+    (JobState::Deadline, "DEADLINE", "DL"),
+    (JobState::Failed, "FAILED", "F"),
+    (JobState::NodeFail, "NODE_FAIL", "NF"),
+    (JobState::OutOfMemory, "OUT_OF_MEMORY", "OOM"),
+    (JobState::Pending, "PENDING", "PD"),
+    (JobState::Preempted, "PREEMPTED", "PR"),
+    (JobState::Requeued, "REQUEUED", "RQ"),
+    (JobState::RequeueFed, "REQUEUE_FED", "RF"),
+    (JobState::RequeueHold, "REQUEUE_HOLD", "RH"),
+    (JobState::Resizing, "RESIZING", "RS"),
+    (JobState::ResvDelHold, "RESV_DEL_HOLD", "RD"),
+    (JobState::Revoked, "REVOKED", "RV"),
+    (JobState::Running, "RUNNING", "R"),
+    (JobState::Signaling, "SIGNALING", "SI"),
+    (JobState::SpecialExit, "SPECIAL_EXIT", "SE"),
+    (JobState::StageOut, "STAGE_OUT", "SO"),
+    (JobState::Stopped, "STOPPED", "ST"),
+    (JobState::Suspended, "SUSPENDED", "S"),
+    (JobState::Timeout, "TIMEOUT", "TO"),
+];
+
+impl JobState {
+    /// Parses a state such as `FAILED` or `CANCELLED by 1234`. A trailing `+`, which sacct adds
+    /// when it cuts a state short, is ignored. States this version doesn't know are kept as
+    /// [`JobState::Other`], with a warning the first time each is seen.
+    pub(crate) fn parse(value: &str) -> Self {
+        let value = value.trim();
+        let (name, reason) = value.split_once(' ').unwrap_or((value, ""));
+        let name = name.trim_end_matches('+');
+        if name == "CANCELLED" {
+            let by = reason
+                .strip_prefix("by ")
+                .and_then(|uid| uid.trim().parse().ok());
+            return JobState::Cancelled { by };
+        }
+        STATES
+            .into_iter()
+            .find(|(_, known, _)| *known == name)
+            .map_or_else(|| Self::unknown(value), |(state, ..)| state)
+    }
+
+    fn unknown(value: &str) -> Self {
+        let mut warned = WARNED.lock().unwrap_or_else(|err| err.into_inner());
+        if warned.insert(value.to_string()) {
+            eprintln!(
+                "{} unknown job state {value}, taken for a job that hasn't finished",
+                "warning:".yellow().bold()
+            );
+        }
+        JobState::Other(value.to_string())
+    }
+
+    /// The state's name in sacct output, without a reason
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            JobState::Cancelled { .. } => "CANCELLED",
+            JobState::Other(value) => value,
+            state => STATES
+                .iter()
+                .find(|(known, ..)| known == state)
+                .map_or("", |(_, name, _)| *name),
+        }
+    }
+
+    /// How the state ended the job. Unknown states aren't taken for failures, as that would
+    /// run failure hooks and resubmit jobs that may have done fine.
+    pub(crate) fn category(&self) -> Category {
+        match self {
+            JobState::Completed => Category::Success,
+            JobState::Cancelled { .. } | JobState::Revoked => Category::Cancelled,
+            JobState::Completing
+            | JobState::Configuring
+            | JobState::Pending
+            | JobState::Requeued
+            | JobState::RequeueFed
+            | JobState::RequeueHold
+            | JobState::Resizing
+            | JobState::ResvDelHold
+            | JobState::Running
+            | JobState::Signaling
+            | JobState::StageOut
+            | JobState::Stopped
+            | JobState::Suspended
+            | JobState::Other(_) => Category::InProgress,
+            JobState::BootFail
+            | JobState::Deadline
+            | JobState::Failed
+            | JobState::NodeFail
+            | JobState::OutOfMemory
+            | JobState::Preempted
+            | JobState::SpecialExit
+            | JobState::Timeout => Category::Failure,
+        }
+    }
+
+    /// Whether the state is what `query` asks for: a state name such as `FAILED` or a short code
+    /// such as `F`, in any case
+    pub(crate) fn matches(&self, query: &str) -> bool {
+        let query = query.trim().to_uppercase();
+        let code = STATES
+            .iter()
+            .find(|(_, name, _)| *name == self.name())
+            .map(|(.., code)| *code);
+        query == self.name() || Some(query.as_str()) == code
+    }
+
+    /// The state coloured by its category
+    pub(crate) fn coloured(&self) -> ColoredString {
+        let text = self.to_string();
+        match self.category() {
+            Category::Success => text.green(),
+            Category::Failure => text.red(),
+            Category::Cancelled => text.yellow(),
+            Category::InProgress => text.cyan(),
+        }
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobState::Cancelled { by: Some(uid) } => write!(f, "CANCELLED by {uid}"),
+            state => f.write_str(state.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! parse_state_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (input, expected, display) = $value;
+                let state = JobState::parse(input);
+                assert_eq!(expected, state);
+                assert_eq!(display, state.to_string());
+            }
+        )*
+        }
+    }
+
+    parse_state_tests! {
+        parse_state0: ("COMPLETED", JobState::Completed, "COMPLETED"),
+        parse_state1: ("CANCELLED by 12345", JobState::Cancelled { by: Some(12345) }, "CANCELLED by 12345"),
+        parse_state2: ("CANCELLED+", JobState::Cancelled { by: None }, "CANCELLED"),
+        parse_state3: ("CANCELLED", JobState::Cancelled { by: None }, "CANCELLED"),
+        parse_state4: ("OUT_OF_MEMORY", JobState::OutOfMemory, "OUT_OF_MEMORY"),
+        parse_state5: ("RESV_DEL_HOLD", JobState::ResvDelHold, "RESV_DEL_HOLD"),
+        parse_state6: ("LAUNCH_FAILED", JobState::Other("LAUNCH_FAILED".to_string()), "LAUNCH_FAILED"),
+    }
+
+    #[test]
+    fn states_round_trip() {
+        for (state, name, _) in STATES {
+            assert_eq!(JobState::parse(name), state);
+            assert_eq!(state.to_string(), name);
+        }
+    }
+
+    #[test]
+    fn categories() {
+        assert_eq!(JobState::Completed.category(), Category::Success);
+        assert_eq!(JobState::Timeout.category(), Category::Failure);
+        assert_eq!(
+            JobState::parse("CANCELLED by 0").category(),
+            Category::Cancelled
+        );
+        assert_eq!(JobState::Pending.category(), Category::InProgress);
+        assert_eq!(JobState::parse("WHATEVER").category(), Category::InProgress);
+    }
+
+    #[test]
+    fn queries_match_names_and_codes() {
+        let cancelled = JobState::parse("CANCELLED by 12345");
+        assert!(cancelled.matches("CANCELLED"));
+        assert!(cancelled.matches("ca"));
+        assert!(JobState::Failed.matches("F"));
+        assert!(JobState::Failed.matches("failed"));
+        assert!(!JobState::Failed.matches("FAILED by 1"));
+        assert!(JobState::Timeout.matches("TO"));
+        assert!(!JobState::Timeout.matches("F"));
+        assert!(JobState::Other("LAUNCH_FAILED".to_string()).matches("launch_failed"));
+    }
+}
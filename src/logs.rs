@@ -0,0 +1,268 @@
+use anyhow::Result;
+use colored::Colorize;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::backend::Backend;
+use crate::{Job, SacctRecord, get_workdir};
+
+/// sacct fields with the paths of a job's output files. Only newer SLURM versions know them.
+const LOG_FIELDS: [&str; 2] = ["stdout", "stderr"];
+
+/// How much of an output file is read at a time while looking for its last lines
+const TAIL_CHUNK_SIZE: u64 = 8192;
+
+/// Where a job wrote its standard output and error
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct LogPaths {
+    pub(crate) stdout: PathBuf,
+    pub(crate) stderr: PathBuf,
+}
+
+impl LogPaths {
+    /// Asks sacct for the job's StdOut and StdErr, falling back to SLURM's default file names in
+    /// the job's working directory when sacct doesn't know them. The working directory is only
+    /// looked up when a path is relative to it.
+    pub(crate) fn resolve(backend: &dyn Backend, job: &Job, user: &str) -> Result<Self> {
+        let jobid = job.jobid_display();
+        let reported = backend
+            .job_details(&jobid, &LOG_FIELDS)
+            .ok()
+            .and_then(|output| {
+                // the job's own line comes first; older versions answer with an error instead
+                let line = output.lines().next()?;
+                let record = SacctRecord::parse(&LOG_FIELDS, line).ok()?;
+                let stdout = record.find("stdout").filter(|path| !path.is_empty())?;
+                let stderr = record.find("stderr").filter(|path| !path.is_empty());
+                Some((stdout.to_string(), stderr.unwrap_or(stdout).to_string()))
+            });
+
+        let (stdout, stderr) = match reported {
+            Some((stdout, stderr)) => (
+                PathBuf::from(expand_pattern(&stdout, job, user)),
+                PathBuf::from(expand_pattern(&stderr, job, user)),
+            ),
+            None => {
+                let path = PathBuf::from(default_file_name(job));
+                (path.clone(), path)
+            }
+        };
+        if stdout.is_absolute() && stderr.is_absolute() {
+            return Ok(LogPaths { stdout, stderr });
+        }
+        let workdir = PathBuf::from(get_workdir(backend, &jobid)?);
+        Ok(LogPaths {
+            stdout: workdir.join(stdout),
+            stderr: workdir.join(stderr),
+        })
+    }
+
+    /// The distinct files, stdout first
+    fn files(&self) -> Vec<&Path> {
+        if self.stdout == self.stderr {
+            vec![&self.stdout]
+        } else {
+            vec![&self.stdout, &self.stderr]
+        }
+    }
+}
+
+/// `slurm-%j.out` for jobs and `slurm-%A_%a.out` for array tasks, as `sbatch` names them when
+/// `--output` isn't given
+fn default_file_name(job: &Job) -> String {
+    match job.array_index {
+        Some(index) => format!("slurm-{}_{index}.out", job.jobid_base),
+        None => format!("slurm-{}.out", job.jobid_base),
+    }
+}
+
+/// Fills in the `sbatch --output` filename patterns that can be derived from `job`. `%j` of an
+/// array task is its own job ID, which isn't known here, so `<base>_<index>` is used instead.
+fn expand_pattern(pattern: &str, job: &Job, user: &str) -> String {
+    let mut expanded = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('j') => expanded.push_str(&job.jobid_display()),
+            Some('A') => expanded.push_str(&job.jobid_base.to_string()),
+            Some('a') => match job.array_index {
+                Some(index) => expanded.push_str(&index.to_string()),
+                None => expanded.push_str("4294967294"),
+            },
+            Some('x') => expanded.push_str(&job.jobname),
+            Some('u') => expanded.push_str(user),
+            Some('%') => expanded.push('%'),
+            // node names, step IDs, ... are left as they are
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+/// The last `lines` lines of the file at `path`, read from the end so huge outputs stay cheap
+pub(crate) fn tail(path: &Path, lines: usize) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let mut start = file.metadata()?.len();
+    let mut buffer = Vec::new();
+    // one more line break than lines wanted guarantees the first of them is complete
+    while start > 0 && buffer.iter().filter(|&&byte| byte == b'\n').count() <= lines {
+        let size = TAIL_CHUNK_SIZE.min(start);
+        start -= size;
+        file.seek(SeekFrom::Start(start))?;
+        let mut chunk = vec![0; size as usize];
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&buffer);
+        buffer = chunk;
+    }
+
+    let text = String::from_utf8_lossy(&buffer);
+    let all: Vec<_> = text.lines().collect();
+    Ok(all[all.len().saturating_sub(lines)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// The last `lines` lines of every output file of `job`, each file introduced by its path, ready
+/// to be shown under the job's row
+pub(crate) fn tail_job(backend: &dyn Backend, job: &Job, user: &str, lines: usize) -> Vec<String> {
+    let paths = match LogPaths::resolve(backend, job, user) {
+        Ok(paths) => paths,
+        Err(err) => return vec![format!("(no output found: {err:#})").dimmed().to_string()],
+    };
+
+    let mut details = Vec::new();
+    for path in paths.files() {
+        match tail(path, lines) {
+            Ok(tail) => {
+                details.push(format!("==> {} <==", path.display()).dimmed().to_string());
+                details.extend(tail);
+            }
+            Err(err) => details.push(
+                format!("(unable to read {}: {err})", path.display())
+                    .dimmed()
+                    .to_string(),
+            ),
+        }
+    }
+    details
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Replay;
+    use crate::job_state::JobState;
+    use std::fs;
+
+    fn job(array_index: Option<usize>) -> Job {
+        Job {
+            jobid_base: 123,
+            array_index,
+            jobname: "relax".to_string(),
+            alloccpus: 1,
+            elapsed: "00:01:00".to_string(),
+            start: None,
+            end: None,
+            state: JobState::Failed,
+            ..Default::default()
+        }
+    }
+
+    /// A replay directory whose only job ran in the directory itself
+    fn replay(dir: &Path, header: &str, line: &str) -> Replay {
+        fs::write(dir.join("jobs.sacct"), format!("{header}\n{line}\n")).unwrap();
+        Replay::new(dir)
+    }
+
+    #[test]
+    fn patterns_are_expanded() {
+        let pattern = "logs/%x-%A_%a.%u.100%%.%N";
+        assert_eq!(
+            expand_pattern(pattern, &job(Some(7)), "me"),
+            "logs/relax-123_7.me.100%.%N"
+        );
+        assert_eq!(expand_pattern("%j.out", &job(None), "me"), "123.out");
+    }
+
+    #[test]
+    fn falls_back_to_default_names_in_workdir() {
+        let dir = tempfile::tempdir().unwrap();
+        let workdir = dir.path().display().to_string();
+        let backend = replay(dir.path(), "JobID|WorkDir", &format!("123_4|{workdir}"));
+        let paths = LogPaths::resolve(&backend, &job(Some(4)), "me").unwrap();
+        assert_eq!(paths.stdout, dir.path().join("slurm-123_4.out"));
+        assert_eq!(paths.files().len(), 1);
+    }
+
+    #[test]
+    fn uses_paths_reported_by_sacct() {
+        let dir = tempfile::tempdir().unwrap();
+        let workdir = dir.path().display().to_string();
+        let backend = replay(
+            dir.path(),
+            "JobID|WorkDir|StdOut|StdErr",
+            &format!("123|{workdir}|{workdir}/%x.%j.out|err/%j.err"),
+        );
+        let paths = LogPaths::resolve(&backend, &job(None), "me").unwrap();
+        assert_eq!(paths.stdout, dir.path().join("relax.123.out"));
+        // relative paths are relative to the working directory
+        assert_eq!(paths.stderr, dir.path().join("err/123.err"));
+    }
+
+    #[test]
+    fn absolute_paths_need_no_workdir() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = dir.path().display().to_string();
+        let backend = replay(
+            dir.path(),
+            "JobID|StdOut|StdErr",
+            &format!("123|{logs}/%j.out|{logs}/%j.err"),
+        );
+        let paths = LogPaths::resolve(&backend, &job(None), "me").unwrap();
+        assert_eq!(paths.stdout, dir.path().join("123.out"));
+        assert_eq!(paths.stderr, dir.path().join("123.err"));
+
+        let backend = replay(dir.path(), "JobID|StdOut", "123|err/%j.out");
+        assert!(LogPaths::resolve(&backend, &job(None), "me").is_err());
+    }
+
+    #[test]
+    fn tail_reads_last_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("slurm-123.out");
+        let contents: String = (0..5000).map(|i| format!("line {i}\n")).collect();
+        fs::write(&path, contents).unwrap();
+        assert_eq!(tail(&path, 2).unwrap(), ["line 4998", "line 4999"]);
+        assert_eq!(tail(&path, 0).unwrap(), Vec::<String>::new());
+
+        fs::write(&path, "only\nthree\nlines").unwrap();
+        assert_eq!(tail(&path, 10).unwrap(), ["only", "three", "lines"]);
+    }
+
+    #[test]
+    fn tail_job_shows_output_under_a_path_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let workdir = dir.path().display().to_string();
+        let backend = replay(dir.path(), "JobID|WorkDir", &format!("123|{workdir}"));
+        fs::write(
+            dir.path().join("slurm-123.out"),
+            "start\nSegmentation fault\n",
+        )
+        .unwrap();
+
+        let details = tail_job(&backend, &job(None), "me", 1);
+        assert_eq!(details.len(), 2);
+        assert!(details[0].contains("slurm-123.out"));
+        assert_eq!(details[1], "Segmentation fault");
+    }
+}
@@ -1,47 +1,186 @@
 use anyhow::{Context, Result, anyhow};
 use chrono::prelude::*;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
+use std::fmt;
 use std::fs;
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 use std::str;
 
+mod backend;
+mod columns;
+mod efficiency;
+mod exit_code;
+mod history;
+mod hooks;
+mod job_state;
+mod logs;
+mod notify;
+mod output;
+mod ranges;
+mod resubmit;
+mod state;
+mod table;
+mod wait;
+mod watch;
+
+use backend::{Backend, Replay, Sacct};
+use colored::ColoredString;
+use columns::Column;
+use efficiency::{RESOURCE_FIELDS, Resources};
+use exit_code::{EXIT_FIELDS, ExitCodes};
+use history::{HistoryFilter, HistoryStore};
+use hooks::Hook;
+use job_state::{Category, JobState};
+use notify::Webhook;
+use output::OutputFormat;
+use ranges::{Ranges, parse_ranges};
+use serde::{Deserialize, Serialize};
+use state::StateDir;
+use std::collections::BTreeMap;
+use table::Table;
+
 const INPUT_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
 const LOG_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 const START_END_FORMAT: &str = "%b-%d %H:%M";
 const FORMAT_CMD: [&str; 7] = [
-    "jobid%20",
-    "jobname%30",
+    "jobid",
+    "jobname",
     "alloccpus",
     "elapsed",
     "start",
     "end",
     "state",
 ];
-const HEADERS: [&str; N_CMDS] = [
-    "Job ID          ",
-    "Job Name                ", // 24 wide, matches WIDTH
-    "CPUs   ",
-    "Elapsed       ",
-    "Start         ",
-    "End            ",
-    "State    ",
+const SACCT_DELIMITER: char = '|';
+/// sacct fields holding text of the user's choosing, which may contain [`SACCT_DELIMITER`] and
+/// are truncated first when a table is too wide
+const FREE_TEXT_FIELDS: [&str; 9] = [
+    "jobname",
+    "submitline",
+    "workdir",
+    "comment",
+    "admincomment",
+    "systemcomment",
+    "stdout",
+    "stderr",
+    "stdin",
 ];
-const N_CMDS: usize = FORMAT_CMD.len();
-const WIDTH: usize = 24;
-const SKIP_STATES: [&str; 2] = ["PENDING", "CANCELLED+"];
+
+/// Job ID, array index and heterogeneous job offset, which together identify a job
+type JobKey = (usize, Option<usize>, Option<usize>);
+/// Arrays with more tasks than this are summarized in a single row unless --expand-arrays is
+/// given
+const ARRAY_COLLAPSE_THRESHOLD: usize = 10;
+/// How much further back than the last session unseen mode looks for jobs
+const UNSEEN_OVERLAP: chrono::TimeDelta = chrono::TimeDelta::days(1);
 
 #[derive(Debug, PartialEq)]
 enum ParsedJobId {
     Singular(usize),
-    Array { base: usize, index: usize },
+    Array {
+        base: usize,
+        index: usize,
+    },
+    /// A step (`.batch`, `.extern`, `.0`, ...) of a singular job, an array task or a component
+    /// of a heterogeneous job
+    Step {
+        base: usize,
+        index: Option<usize>,
+    },
+    /// Array tasks that haven't started yet, listed together as `123_[1-1000%50]`
+    PendingArray {
+        base: usize,
+        indices: Ranges,
+        max_running: Option<usize>,
+    },
+    /// A component of a heterogeneous job, `123+0`, where `leader` is the job ID of its first
+    /// component
+    HetComponent {
+        leader: usize,
+        offset: usize,
+    },
     NotJob,
 }
 
+/// A step of a job (`batch`, `extern`, `0`, ...), with its own state and resource use
+#[derive(Debug)]
+struct JobStep {
+    /// What follows the `.` in the step's job ID
+    id: String,
+    job: Job,
+}
+
+/// Jobs that haven't finished, or were cancelled, listed apart from the finished ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Section {
+    Running,
+    Pending,
+    Cancelled,
+}
+
+impl Section {
+    const ALL: [Section; 3] = [Section::Running, Section::Pending, Section::Cancelled];
+
+    fn heading(self) -> &'static str {
+        match self {
+            Section::Running => "Running jobs",
+            Section::Pending => "Pending jobs",
+            Section::Cancelled => "Cancelled jobs",
+        }
+    }
+}
+
+/// How jobs are laid out in the table
+#[derive(Debug, Clone, Default)]
+struct TableOptions {
+    /// List every task of large array jobs instead of summarizing them
+    expand_arrays: bool,
+    /// Show the steps of every job under it
+    steps: bool,
+    /// Show jobs of every section among the finished ones, as --state picked the states to show
+    show_skipped: bool,
+    /// Sections to list after the finished jobs
+    include: Vec<Section>,
+}
+
+impl TableOptions {
+    /// Whether `job` is shown in `section`, or among the finished jobs for `None`
+    fn shows(&self, job: &Job, section: Option<Section>) -> bool {
+        if self.show_skipped {
+            section.is_none()
+        } else {
+            job.section() == section
+        }
+    }
+
+    /// Whether `job` is shown in any section
+    fn lists(&self, job: &Job) -> bool {
+        self.show_skipped
+            || job
+                .section()
+                .is_none_or(|section| self.include.contains(&section))
+    }
+}
+
+/// Array tasks that sacct lists as a single record because they haven't started yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PendingTasks {
+    indices: Ranges,
+    /// The `%` throttle: how many of the tasks may run at once
+    max_running: Option<usize>,
+}
+
+impl fmt::Display for PendingTasks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}", self.indices)?;
+        if let Some(max_running) = self.max_running {
+            write!(f, "%{max_running}")?;
+        }
+        write!(f, "]")
+    }
+}
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
 struct Args {
@@ -49,8 +188,8 @@ struct Args {
     #[clap(long, conflicts_with_all = ["since", "hours", "days"])]
     day: bool,
 
-    /// Get finished jobs since a specific time (YYYY-MM-DDTHH:MM:SS)
-    #[clap(long, value_name = "YYYY-MM-DDTHH:MM:SS", conflicts_with_all = ["hours", "days"])]
+    /// Get finished jobs since a specific time (YYYY-MM-DD[THH:MM:SS])
+    #[clap(long, value_name = "YYYY-MM-DD[THH:MM:SS]", conflicts_with_all = ["hours", "days"])]
     since: Option<String>,
 
     /// Get finished jobs from othe last N hours
@@ -63,13 +202,185 @@ struct Args {
     #[clap(short, long, default_value_t = default_user())]
     user: String,
 
-    /// Filter output to only show jobs with these states (e.g. FAILED, COMPLETED, TIMEOUT)
-    #[clap(short, long, value_name = "STATE")]
+    /// Filter output to only show jobs with these states (e.g. FAILED, COMPLETED, TIMEOUT), or
+    /// their short codes (F, CD, TO, ...)
+    #[clap(short, long, value_name = "STATE", global = true)]
     state: Vec<String>,
 
     /// Show the working directory of a job given its job ID
     #[clap(short, long, value_name = "jobid")]
     workdir: Option<String>,
+
+    /// Comma-separated sacct fields to show, e.g. jobid,jobname,partition,maxrss,exitcode,state.
+    /// Fields jobs_done doesn't know are shown as plain text.
+    #[clap(
+        short,
+        long,
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        default_values_t = columns::default_columns(),
+        global = true
+    )]
+    columns: Vec<String>,
+
+    /// Output format of the job list
+    #[clap(short, long, value_enum, default_value_t, global = true)]
+    output: OutputFormat,
+
+    /// Only show finished jobs that haven't been shown in this mode (or acknowledged) before,
+    /// then mark them as seen
+    #[clap(long)]
+    unseen: bool,
+
+    /// Like --unseen, but without marking the jobs as seen
+    #[clap(long)]
+    peek: bool,
+
+    /// Directory for the session date and job history
+    /// [default: $XDG_STATE_HOME/jobs_done/<cluster>/<user>]
+    #[clap(long, value_name = "DIR", env = "JOBS_DONE_STATE_DIR", global = true)]
+    state_dir: Option<PathBuf>,
+
+    /// Add CPU and memory efficiency and time-limit utilization columns, like `seff` reports
+    #[clap(long, global = true)]
+    efficiency: bool,
+
+    /// Show every task of large array jobs on its own row instead of summarizing them
+    #[clap(long, global = true)]
+    expand_arrays: bool,
+
+    /// Show the steps of every job (batch, extern, each srun) under it, with their own state,
+    /// elapsed time, exit code and MaxRSS
+    #[clap(long, global = true)]
+    steps: bool,
+
+    /// Also list jobs in these states, each in a section of its own after the finished jobs
+    #[clap(
+        long,
+        value_enum,
+        value_name = "SECTIONS",
+        value_delimiter = ',',
+        global = true
+    )]
+    include: Vec<Section>,
+
+    /// Also list running, pending and cancelled jobs, like --include running,pending,cancelled
+    #[clap(long, global = true, conflicts_with = "include")]
+    all: bool,
+
+    /// Print the last N lines of the output of every job that failed under its row in the table
+    #[clap(long, value_name = "N")]
+    tail: Option<usize>,
+
+    /// Run COMMAND with `sh` once for every finished job in STATE (or `ANY` state), e.g.
+    /// `--hook 'COMPLETED=rsync -a "$JOBS_DONE_WORKDIR" backup:'`. The job is described by
+    /// JOBS_DONE_JOBID, _JOBNAME, _STATE, _WORKDIR, _EXIT_CODE and _EXIT_SIGNAL. Runs are
+    /// recorded, so a hook never runs twice for the same job.
+    #[clap(long, value_name = "STATE=COMMAND", value_parser = Hook::parse, global = true)]
+    hook: Vec<Hook>,
+
+    /// Post newly finished jobs to this incoming webhook URL (Slack, Mattermost, Matrix
+    /// hookshot, ...), batched into as few messages as possible. Each job is announced once.
+    #[clap(long, value_name = "URL", env = "JOBS_DONE_WEBHOOK", global = true)]
+    webhook: Option<String>,
+
+    /// JSON payload to post to --webhook instead of `{"text": ..., "jobs": [...]}`, where
+    /// `{text}` is replaced by the message as a JSON string, `{jobs}` by the jobs as in
+    /// `--output json` and `{count}` by their number
+    #[clap(long, value_name = "TEMPLATE", requires = "webhook", global = true)]
+    webhook_template: Option<String>,
+
+    /// Replay sacct output recorded in DIR instead of querying SLURM
+    #[clap(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Search the jobs recorded locally by previous runs, without querying SLURM
+    History {
+        /// Only show jobs whose name matches this glob (`*` and `?` wildcards)
+        #[clap(long, value_name = "PATTERN")]
+        name: Option<String>,
+
+        /// Only show jobs that finished at or after this time
+        #[clap(long, value_name = "YYYY-MM-DD[THH:MM:SS]")]
+        since: Option<String>,
+    },
+    /// Mark finished jobs as seen so --unseen won't show them again
+    Ack {
+        /// Job IDs, array task IDs (`123_4`) or array job IDs (`123`, all tasks) to mark as seen.
+        /// Marks every unseen job if none are given.
+        jobids: Vec<String>,
+    },
+    /// Keep polling SLURM and print every job as soon as it finishes, until Ctrl-C
+    Watch {
+        /// Seconds between polls; longer while sacct is slow or failing
+        #[clap(long, value_name = "SECONDS", default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Block until the given jobs have finished, then print them. Exits with 0 if all of them
+    /// completed, 2 if any failed, 3 if some only reached their time limit and 124 on --timeout.
+    Wait {
+        /// Job IDs, array task IDs (`123_4`), task ranges (`123_[1-10]`), components of
+        /// heterogeneous jobs (`123+1`) or array job IDs (`123`, all tasks) to wait for
+        #[clap(required = true)]
+        jobids: Vec<String>,
+
+        /// Give up after this many seconds
+        #[clap(long, value_name = "SECONDS")]
+        timeout: Option<u64>,
+
+        /// Seconds between polls; longer while sacct is slow or failing
+        #[clap(long, value_name = "SECONDS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Submit finished jobs again from their working directory, with the batch script and
+    /// options they were originally submitted with
+    Resubmit {
+        /// Job IDs, array task IDs (`123_4`) or array job IDs (`123`, all tasks) to resubmit.
+        /// Without them, every job that finished since --since and failed or timed out (or is
+        /// in one of the given --state) is resubmitted.
+        #[clap(required_unless_present = "since")]
+        jobids: Vec<String>,
+
+        /// Resubmit jobs that finished at or after this time
+        #[clap(long, value_name = "YYYY-MM-DD[THH:MM:SS]")]
+        since: Option<String>,
+
+        /// Only print the commands that would submit the jobs
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Time limit to submit with instead of the original one
+        #[clap(long, value_name = "TIME")]
+        time: Option<String>,
+
+        /// Memory to submit with instead of the original amount
+        #[clap(long, value_name = "SIZE")]
+        mem: Option<String>,
+    },
+    /// Submit the tasks of an array job that failed or timed out (or are in one of the given
+    /// --state) again, as one new array job with just their indices
+    ResubmitArray {
+        /// ID of the array job, without a task index
+        jobid: String,
+
+        /// Only print the command that would submit the tasks
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Time limit to submit with instead of the original one
+        #[clap(long, value_name = "TIME")]
+        time: Option<String>,
+
+        /// Memory to submit with instead of the original amount
+        #[clap(long, value_name = "SIZE")]
+        mem: Option<String>,
+    },
 }
 
 fn default_user() -> String {
@@ -85,57 +396,232 @@ struct Job {
     elapsed: String,
     start: Option<NaiveDateTime>,
     end: Option<NaiveDateTime>,
-    state: String,
+    state: JobState,
+    resources: Resources,
+    exit_codes: ExitCodes,
+    /// Requested sacct fields without a member of their own, by lower-case field name
+    extra: BTreeMap<String, String>,
+    /// Offset of this component of a heterogeneous job, whose leader is `jobid_base`
+    het_offset: Option<usize>,
+    /// Set if this record stands for array tasks of `jobid_base` that haven't started yet
+    pending_tasks: Option<PendingTasks>,
+    steps: Vec<JobStep>,
 }
 impl PartialEq for Job {
     fn eq(&self, other: &Self) -> bool {
-        self.jobid_base == other.jobid_base && self.array_index == other.array_index
+        self.key() == other.key()
     }
 }
 impl Eq for Job {}
 
+/// A completed job with nothing else known about it, for tests to fill in what they need
+#[cfg(test)]
+impl Default for Job {
+    fn default() -> Self {
+        Job {
+            jobid_base: 0,
+            array_index: None,
+            jobname: String::new(),
+            alloccpus: 0,
+            elapsed: "00:00:00".to_string(),
+            start: None,
+            end: None,
+            state: JobState::Completed,
+            resources: Resources::default(),
+            exit_codes: ExitCodes::default(),
+            extra: BTreeMap::new(),
+            het_offset: None,
+            pending_tasks: None,
+            steps: Vec::new(),
+        }
+    }
+}
+
+/// A single line of `sacct -P` output with its fields addressed by name
+#[derive(Debug)]
+struct SacctRecord<'a> {
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> SacctRecord<'a> {
+    /// Splits `line` on [`SACCT_DELIMITER`] and pairs each value with the field name it was
+    /// requested under. sacct doesn't escape the delimiter, so surplus values are put back into
+    /// the free-text field if only one was requested. Fails if the values still don't match
+    /// the names.
+    fn parse(names: &[&'a str], line: &'a str) -> Result<Self> {
+        let count = line.split(SACCT_DELIMITER).count();
+        let free_text: Vec<_> = (0..names.len())
+            .filter(|&index| FREE_TEXT_FIELDS.contains(&names[index]))
+            .collect();
+        let values: Vec<_> = match free_text[..] {
+            [index] if count > names.len() => {
+                // the fields before the free-text one from the left, those after it from the
+                // right, and what's left in between is the free text itself
+                let mut head: Vec<_> = line.splitn(index + 1, SACCT_DELIMITER).collect();
+                let rest = head.pop().unwrap_or_default();
+                let mut tail: Vec<_> = rest.rsplitn(names.len() - index, SACCT_DELIMITER).collect();
+                tail.reverse();
+                head.extend(tail);
+                head
+            }
+            _ => line.split(SACCT_DELIMITER).collect(),
+        };
+        if values.len() != names.len() {
+            return Err(anyhow!(
+                "expected {} fields but found {}",
+                names.len(),
+                values.len()
+            ));
+        }
+        Ok(SacctRecord {
+            fields: names.iter().copied().zip(values).collect(),
+        })
+    }
+
+    fn get(&self, name: &str) -> Result<&'a str> {
+        self.find(name)
+            .ok_or_else(|| anyhow!("sacct output is missing the `{name}` field"))
+    }
+
+    /// Like [`SacctRecord::get`], for fields that are optional
+    fn find(&self, name: &str) -> Option<&'a str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Parses a sacct duration (`[DD-][HH:]MM:SS[.mmm]`) into whole seconds
+fn parse_duration(value: &str) -> Option<u64> {
+    let (days, clock) = match value.split_once('-') {
+        Some((days, clock)) => (days.parse::<u64>().ok()?, clock),
+        None => (0, value),
+    };
+    let mut parts = clock.rsplit(':');
+    // fractional seconds only show up for sub-hour CPU times; they're dropped
+    let seconds = parts.next()?.split('.').next()?.parse::<u64>().ok()?;
+    let minutes = parts.next().map_or(Some(0), |m| m.parse::<u64>().ok())?;
+    let hours = parts.next().map_or(Some(0), |h| h.parse::<u64>().ok())?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(((days * 24 + hours) * 60 + minutes) * 60 + seconds)
+}
+
+/// Formats seconds as a sacct duration, `[DD-]HH:MM:SS`
+fn format_duration(seconds: u64) -> String {
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    let clock = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if days > 0 {
+        format!("{days}-{clock}")
+    } else {
+        clock
+    }
+}
+
+/// Parses a `--since` value: either a full timestamp or a date, meaning midnight of that day
+fn parse_since(value: &str) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, INPUT_DATE_FORMAT)
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| date.and_time(NaiveTime::MIN))
+        })
+        .context("unable to parse --since with expected format")
+}
+
+/// Parses a start/end timestamp, mapping sacct's placeholders to `None`
+fn parse_sacct_date(value: &str, date_format: &str) -> Result<Option<NaiveDateTime>> {
+    match value {
+        // placeholder value as the job is not yet (UNKNOWN)/was never (NONE) started or is
+        // unfinished. Empty fields show up for jobs that never got that far.
+        "" | "Unknown" | "None" => Ok(None),
+        _ => Ok(Some(NaiveDateTime::parse_from_str(value, date_format)?)),
+    }
+}
+
+/// Whether the sacct field `name` has a member of its own in [`Job`], rather than going to `extra`
+fn is_job_field(name: &str) -> bool {
+    FORMAT_CMD.contains(&name) || RESOURCE_FIELDS.contains(&name) || EXIT_FIELDS.contains(&name)
+}
+
 impl Job {
     fn parse_job(
         jobid_base: usize,
         array_index: Option<usize>,
-        lines: &[&str],
+        record: &SacctRecord,
         date_format: &str,
     ) -> Result<Self> {
+        let alloccpus = record
+            .get("alloccpus")?
+            .parse::<usize>()
+            .context("could not parse alloccpus")?;
         Ok(Job {
             jobid_base,
             array_index,
-            jobname: lines[1].to_string(),
-            alloccpus: lines[2]
-                .parse::<usize>()
-                .context("could not parse alloccpus")?,
-            elapsed: lines[3].to_string(),
-            start: match lines[4] {
-                // placeholder value as the job is not yet (UNKNOWN)/was never (NONE) started
-                "Unknown" | "None" => None,
-                _ => Some(
-                    NaiveDateTime::parse_from_str(lines[4], date_format)
-                        .context("unable to parse start")?,
-                ),
-            },
-            end: match lines[5] {
-                // placeholder value due to the job being unfinished
-                "Unknown" => None,
-                _ => Some(
-                    NaiveDateTime::parse_from_str(lines[5], date_format)
-                        .context("unable to parse end")?,
-                ),
-            },
-            state: lines[6].to_string(),
+            jobname: record.get("jobname")?.to_string(),
+            alloccpus,
+            elapsed: record.get("elapsed")?.to_string(),
+            start: parse_sacct_date(record.get("start")?, date_format)
+                .context("unable to parse start")?,
+            end: parse_sacct_date(record.get("end")?, date_format)
+                .context("unable to parse end")?,
+            state: JobState::parse(record.get("state")?),
+            resources: Resources::parse(record, alloccpus),
+            exit_codes: ExitCodes::parse(record),
+            extra: record
+                .fields
+                .iter()
+                .filter(|(name, _)| !is_job_field(name))
+                .map(|(name, value)| (name.to_lowercase(), value.to_string()))
+                .collect(),
+            het_offset: None,
+            pending_tasks: None,
+            steps: Vec::new(),
         })
     }
 
+    /// Identifies the job among all others: job ID, array index and heterogeneous job offset
+    fn key(&self) -> JobKey {
+        (self.jobid_base, self.array_index, self.het_offset)
+    }
+
+    /// Whether the job has reached its final state
+    fn is_finished(&self) -> bool {
+        self.state.category() != Category::InProgress
+    }
+
+    /// The section the job is listed in, or `None` if it finished and is listed with the other
+    /// finished jobs
+    fn section(&self) -> Option<Section> {
+        match self.state.category() {
+            Category::Success | Category::Failure => None,
+            Category::Cancelled => Some(Section::Cancelled),
+            Category::InProgress if self.state == JobState::Pending => Some(Section::Pending),
+            Category::InProgress => Some(Section::Running),
+        }
+    }
+
+    /// Jobs that haven't finished and cancelled ones are skipped unless asked for
     fn is_displayable(&self) -> bool {
-        !SKIP_STATES.iter().any(|&x| self.state == x)
+        self.section().is_none()
+    }
+
+    fn elapsed_seconds(&self) -> Option<u64> {
+        parse_duration(&self.elapsed)
     }
 
     fn jobid_display(&self) -> String {
         if let Some(idx) = self.array_index {
             format!("{}_{}", self.jobid_base, idx)
+        } else if let Some(offset) = self.het_offset {
+            format!("{}+{}", self.jobid_base, offset)
+        } else if let Some(pending) = &self.pending_tasks {
+            format!("{}_{pending}", self.jobid_base)
         } else {
             self.jobid_base.to_string()
         }
@@ -143,58 +629,65 @@ impl Job {
 }
 
 /// Gets the working directory of a job given its jobid
-fn get_workdir(jobid: &str) -> Result<String> {
-    let output = Command::new("sacct")
-        .args(["-n", "-P", "-j", jobid])
-        .arg("--format=workdir%170")
-        .output()
-        .context("failed to execute sacct")?;
-    let bytes = if output.status.success() {
-        output.stdout
-    } else {
-        output.stderr
-    };
-    let sacct_output = String::from_utf8(bytes).context("sacct output contained invalid UTF-8")?;
+fn get_workdir(backend: &dyn Backend, jobid: &str) -> Result<String> {
+    let sacct_output = backend.job_details(jobid, &["workdir"])?;
 
-    let trimmed_output = sacct_output.trim();
-    if trimmed_output.is_empty() {
-        Err(anyhow!("Couldn't find working directory for job {jobid}"))
-    } else {
-        Ok(trimmed_output.to_string())
+    // the job's own line comes first; its steps have no workdir of their own
+    match sacct_output.lines().map(str::trim).find(|l| !l.is_empty()) {
+        Some(workdir) => Ok(workdir.to_string()),
+        None => Err(anyhow!("Couldn't find working directory for job {jobid}")),
     }
 }
 
-fn call_sacct(format_cmd: [&str; 7], window_start: NaiveDateTime, user: &str) -> Result<String> {
-    let output = Command::new("sacct")
-        .args([
-            "-u",
-            user,
-            "-n",
-            "-S",
-            &window_start.format(INPUT_DATE_FORMAT).to_string(),
-        ])
-        .arg(format!("--format={}", format_cmd.join(",")))
-        .output()
-        .context("failed to execute sacct")?;
-
-    let bytes = if output.status.success() {
-        output.stdout
-    } else {
-        output.stderr
-    };
-    String::from_utf8(bytes).context("sacct output contained invalid UTF-8")
-}
-
 fn check_job(line: &str) -> ParsedJobId {
     // if the jobid contains '.' it's a sub-job (batch, extern, etc.)
-    if line.contains('.') {
-        return ParsedJobId::NotJob;
+    if let Some((job, step)) = line.split_once('.') {
+        if step.is_empty() || !step.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return ParsedJobId::NotJob;
+        }
+        return match check_job(job) {
+            ParsedJobId::Singular(base) => ParsedJobId::Step { base, index: None },
+            ParsedJobId::Array { base, index } => ParsedJobId::Step {
+                base,
+                index: Some(index),
+            },
+            ParsedJobId::HetComponent { leader, .. } => ParsedJobId::Step {
+                base: leader,
+                index: None,
+            },
+            _ => ParsedJobId::NotJob,
+        };
     }
 
     if let Some((job_base, array_index)) = line.split_once('_') {
-        if let (Ok(base), Ok(index)) = (job_base.parse::<usize>(), array_index.parse::<usize>()) {
+        let Ok(base) = job_base.parse::<usize>() else {
+            return ParsedJobId::NotJob;
+        };
+        if let Ok(index) = array_index.parse::<usize>() {
             return ParsedJobId::Array { base, index };
         }
+        if array_index.starts_with('[') && array_index.ends_with(']') {
+            let max_running = match array_index.trim_end_matches(']').split_once('%') {
+                Some((_, limit)) => match limit.parse::<usize>() {
+                    Ok(limit) => Some(limit),
+                    Err(_) => return ParsedJobId::NotJob,
+                },
+                None => None,
+            };
+            if let Ok(indices) = parse_ranges(array_index) {
+                return ParsedJobId::PendingArray {
+                    base,
+                    indices,
+                    max_running,
+                };
+            }
+        }
+        return ParsedJobId::NotJob;
+    }
+    if let Some((leader, offset)) = line.split_once('+') {
+        if let (Ok(leader), Ok(offset)) = (leader.parse::<usize>(), offset.parse::<usize>()) {
+            return ParsedJobId::HetComponent { leader, offset };
+        }
         return ParsedJobId::NotJob;
     }
     if let Ok(id) = line.parse::<usize>() {
@@ -204,225 +697,802 @@ fn check_job(line: &str) -> ParsedJobId {
     ParsedJobId::NotJob
 }
 
-fn get_finished_jobs(sacct_output: &str) -> Result<Vec<Job>> {
+/// Parses `sacct -P` output requested with `fields` (which must include `FORMAT_CMD`) into the
+/// jobs that aren't running anymore
+fn get_finished_jobs(sacct_output: &str, fields: &[&str]) -> Result<Vec<Job>> {
+    let mut jobs = parse_jobs(sacct_output, fields)?;
+    jobs.retain(|job| job.state != JobState::Running);
+    Ok(jobs)
+}
+
+/// Parses `sacct -P` output requested with `fields` (which must include `FORMAT_CMD`) into jobs
+/// in any state, one record per line. Steps are folded into the job listed right before them.
+fn parse_jobs(sacct_output: &str, fields: &[&str]) -> Result<Vec<Job>> {
     let mut jobs: Vec<Job> = Vec::new();
-    let split_output: Vec<_> = sacct_output.split_whitespace().collect();
 
-    for chunked_lines in split_output.chunks(N_CMDS) {
-        let parsed_jobid = check_job(chunked_lines[0]);
+    for (line_idx, line) in sacct_output.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = line_idx + 1;
+        let record = match SacctRecord::parse(fields, line) {
+            Ok(record) => record,
+            Err(err) => {
+                eprintln!(
+                    "{} skipping malformed sacct output on line {line_number} ({err:#}): {line:?}",
+                    "warning:".yellow().bold()
+                );
+                continue;
+            }
+        };
+
+        let parsed_jobid = check_job(record.get("jobid")?);
+        let (mut het_offset, mut pending_tasks) = (None, None);
         let (base_id, array_index) = match parsed_jobid {
             ParsedJobId::Singular(id) => (id, None),
             ParsedJobId::Array { base, index } => (base, Some(index)),
+            ParsedJobId::HetComponent { leader, offset } => {
+                het_offset = Some(offset);
+                (leader, None)
+            }
+            ParsedJobId::PendingArray {
+                base,
+                indices,
+                max_running,
+            } => {
+                pending_tasks = Some(PendingTasks {
+                    indices,
+                    max_running,
+                });
+                (base, None)
+            }
+            ParsedJobId::Step { base, index } => {
+                let parent = jobs
+                    .last_mut()
+                    .filter(|job| job.jobid_base == base && job.array_index == index);
+                if let Some(job) = parent {
+                    job.resources.merge_step(&record);
+                    // steps are only shown with --steps, one that can't be parsed isn't worth
+                    // failing over
+                    let id = record.get("jobid")?.split_once('.').map(|(_, id)| id);
+                    let step = Job::parse_job(base, index, &record, INPUT_DATE_FORMAT);
+                    if let (Some(id), Ok(step)) = (id, step) {
+                        job.steps.push(JobStep {
+                            id: id.to_string(),
+                            job: step,
+                        });
+                    }
+                }
+                continue;
+            }
             ParsedJobId::NotJob => continue,
         };
-        let job = Job::parse_job(base_id, array_index, chunked_lines, INPUT_DATE_FORMAT)?;
-        if job.state != "RUNNING" {
-            jobs.push(job);
-        }
+        let mut job = match Job::parse_job(base_id, array_index, &record, INPUT_DATE_FORMAT) {
+            Ok(job) => job,
+            Err(err) => {
+                eprintln!(
+                    "{} skipping job on sacct output line {line_number}: {err:#}",
+                    "warning:".yellow().bold()
+                );
+                continue;
+            }
+        };
+        job.het_offset = het_offset;
+        job.pending_tasks = pending_tasks;
+        jobs.push(job);
     }
 
     Ok(jobs)
 }
 
-fn format_job_line(jobid: &str, job: &Job, indent: &str) -> String {
-    let jobname = &job.jobname;
-    let alloccpus = job.alloccpus;
-    let elapsed = &job.elapsed;
-    let start = if let Some(job_start) = job.start {
-        job_start.format(START_END_FORMAT).to_string().white()
-    } else {
-        "NOT STARTED".yellow()
-    };
-    let end = if let Some(job_end) = job.end {
-        job_end.format(START_END_FORMAT).to_string().white()
-    } else {
-        "UNKNOWN".yellow()
-    };
-    let state = if job.state == "COMPLETED" {
-        job.state.green()
-    } else {
-        job.state.red()
-    };
-    let id_width = 15 - indent.len();
-    format!(
-        "{indent}{jobid:<id_width$} {jobname:jobname_width$} {alloccpus:<6} {elapsed:<13} {start:<13} {end:<14} {state}",
-        jobname_width = WIDTH - 1
-    )
+fn job_cells(jobid: &str, job: &Job, columns: &[Column]) -> Vec<ColoredString> {
+    columns
+        .iter()
+        .map(|column| match column.name.as_str() {
+            "jobid" => jobid.normal(),
+            _ => column.display_value(job),
+        })
+        .collect()
+}
+
+/// Lines shown under a job's row, by job ID as displayed
+type Details = BTreeMap<String, Vec<String>>;
+
+/// Summary lines for the `tasks` of an array job shown as a single row: which indices ended in
+/// which state, and how long they ran. Tasks that haven't started yet count as `PENDING`.
+fn array_summary(tasks: &[&Job], pending: &[&PendingTasks]) -> Vec<String> {
+    let mut task_indices: BTreeMap<&JobState, Vec<usize>> = BTreeMap::new();
+    for task in tasks {
+        task_indices
+            .entry(&task.state)
+            .or_default()
+            .extend(task.array_index);
+    }
+    let mut by_state: BTreeMap<&JobState, Ranges> = task_indices
+        .into_iter()
+        .map(|(state, indices)| (state, indices.into_iter().collect()))
+        .collect();
+    for tasks in pending {
+        by_state
+            .entry(&JobState::Pending)
+            .or_default()
+            .extend(&tasks.indices);
+    }
+    let count: usize = by_state.values().map(Ranges::count).sum();
+    let states: Vec<_> = by_state
+        .iter()
+        .map(|(state, indices)| format!("{} {indices}", state.coloured()))
+        .collect();
+    let mut summary = vec![format!("{count} tasks: {}", states.join("; "))];
+
+    let mut elapsed: Vec<_> = tasks
+        .iter()
+        .filter_map(|task| task.elapsed_seconds())
+        .collect();
+    elapsed.sort_unstable();
+    if let (Some(&min), Some(&max)) = (elapsed.first(), elapsed.last()) {
+        let median = elapsed[(elapsed.len() - 1) / 2];
+        summary.push(format!(
+            "elapsed: min {}, median {}, max {}",
+            format_duration(min),
+            format_duration(median),
+            format_duration(max)
+        ));
+    }
+    summary
+}
+
+/// Cells of the single row standing for array tasks that haven't started yet
+fn pending_cells(tasks: &PendingTasks, columns: &[Column]) -> Vec<ColoredString> {
+    columns
+        .iter()
+        .map(|column| match column.name.as_str() {
+            "jobid" => tasks.to_string().normal(),
+            "jobname" => format!("{} tasks not yet started", tasks.indices.count()).normal(),
+            "state" => JobState::Pending.coloured(),
+            _ => "".normal(),
+        })
+        .collect()
 }
 
-fn create_print(jobs: &[Job]) -> Vec<String> {
-    let mut job_messages: Vec<_> = Vec::with_capacity(32);
+/// Whether `job` is shown under a header row for its array job or heterogeneous job
+fn is_grouped(job: &Job) -> bool {
+    job.array_index.is_some() || job.pending_tasks.is_some() || job.het_offset.is_some()
+}
+
+/// Adds `job`'s row, nested `depth` levels deep, with the steps of the job under it if
+/// `options.steps` is set and then its details
+fn push_job(
+    table: &mut Table,
+    depth: usize,
+    jobid: &str,
+    job: &Job,
+    columns: &[Column],
+    details: &Details,
+    options: &TableOptions,
+) {
+    table.push(depth, job_cells(jobid, job, columns));
+    if options.steps {
+        for step in &job.steps {
+            let step_id = format!(".{}", step.id);
+            table.push(depth + 1, job_cells(&step_id, &step.job, columns));
+        }
+    }
+    if let Some(lines) = details.get(&job.jobid_display()) {
+        table.push_details(lines.iter().cloned());
+    }
+}
+
+/// Table of the `jobs` in `section`, or of the finished ones for `None`
+fn create_print<'a>(
+    jobs: &[Job],
+    columns: &'a [Column],
+    details: &Details,
+    options: &TableOptions,
+    section: Option<Section>,
+) -> Table<'a> {
+    let mut table = Table::new(columns);
     let mut i = 0;
     while i < jobs.len() {
         let job = &jobs[i];
-        if !job.is_displayable() {
+        if !is_grouped(job) {
+            if options.shows(job, section) {
+                let jobid = job.jobid_display();
+                push_job(&mut table, 0, &jobid, job, columns, details, options);
+            }
             i += 1;
             continue;
         }
 
-        if job.array_index.is_some() {
-            let base = job.jobid_base;
-            let jobname = &job.jobname;
+        // Collect the array tasks or heterogeneous job components
+        let base = job.jobid_base;
+        let start = i;
+        while i < jobs.len() && jobs[i].jobid_base == base && is_grouped(&jobs[i]) {
+            i += 1;
+        }
+        let members = &jobs[start..i];
+        let children: Vec<_> = members
+            .iter()
+            .filter(|job| job.pending_tasks.is_none() && options.shows(job, section))
+            .collect();
+        let pending: Vec<_> = members
+            .iter()
+            .filter_map(|job| job.pending_tasks.as_ref())
+            .collect();
+        // tasks that haven't started yet are shown under the tasks that have, or on their own
+        // among the pending jobs
+        let pending_shown = members
+            .iter()
+            .any(|job| job.pending_tasks.is_some() && options.shows(job, section));
+        let Some(first) = children.first().copied().or_else(|| {
+            pending_shown
+                .then(|| members.iter().find(|job| job.pending_tasks.is_some()))
+                .flatten()
+        }) else {
+            continue;
+        };
 
-            // Collect printable array elements
-            let start = i;
-            let mut has_printable = false;
-            while i < jobs.len() && jobs[i].jobid_base == base && jobs[i].array_index.is_some() {
-                if jobs[i].is_displayable() {
-                    has_printable = true;
-                }
-                i += 1;
+        // Parent header: jobid_base and jobname, no state
+        let cells = columns.iter().map(|column| match column.name.as_str() {
+            "jobid" => base.to_string().normal(),
+            "jobname" => first.jobname.normal(),
+            _ => "".normal(),
+        });
+        table.push(0, cells);
+
+        let task_count = children.len()
+            + pending
+                .iter()
+                .map(|tasks| tasks.indices.count())
+                .sum::<usize>();
+        if first.het_offset.is_none()
+            && !options.expand_arrays
+            && task_count > ARRAY_COLLAPSE_THRESHOLD
+        {
+            table.push_details(array_summary(&children, &pending));
+            // tasks with details, e.g. the failed ones with --tail, get their own row to tell
+            // whose details they are
+            for child in children
+                .iter()
+                .filter(|child| details.contains_key(&child.jobid_display()))
+            {
+                let child_id = child
+                    .array_index
+                    .map_or_else(String::new, |index| index.to_string());
+                push_job(&mut table, 1, &child_id, child, columns, details, options);
             }
+            continue;
+        }
 
-            if has_printable {
-                // Parent header: jobid_base and jobname, no state
-                let header = format!(
-                    "{:<15} {jobname:jobname_width$}",
-                    base,
-                    jobname_width = WIDTH - 1
-                );
-                job_messages.push(header);
+        // Indented child lines
+        for child in children {
+            let child_id = match (child.array_index, child.het_offset) {
+                (Some(index), _) => index.to_string(),
+                (None, Some(offset)) => format!("+{offset}"),
+                (None, None) => child.jobid_display(),
+            };
+            push_job(&mut table, 1, &child_id, child, columns, details, options);
+        }
+        for tasks in pending {
+            table.push(1, pending_cells(tasks, columns));
+        }
+    }
 
-                // Indented child lines
-                for child in &jobs[start..i] {
-                    if child.is_displayable() {
-                        let child_id = format!("{}", child.array_index.unwrap());
-                        job_messages.push(format_job_line(&child_id, child, "  "));
-                    }
-                }
-            }
-        } else {
-            let jobid = job.jobid_display();
-            job_messages.push(format_job_line(&jobid, job, ""));
-            i += 1;
+    table
+}
+
+/// Fetches and parses the jobs in the window with the given sacct `fields` (which must include
+/// `FORMAT_CMD`), keeping only the requested states. Running jobs are left out unless `states`
+/// asks for them.
+fn collect_jobs(
+    backend: &dyn Backend,
+    window_start: NaiveDateTime,
+    user: &str,
+    states: &[String],
+    fields: &[&str],
+) -> Result<Vec<Job>> {
+    if !states.is_empty() {
+        return collect_all_jobs(backend, window_start, user, states, fields);
+    }
+    let sacct_output = backend.query_jobs(fields, window_start, user)?;
+    get_finished_jobs(&sacct_output, fields)
+}
+
+/// Like [`collect_jobs`], but keeps the jobs that are still running
+fn collect_all_jobs(
+    backend: &dyn Backend,
+    window_start: NaiveDateTime,
+    user: &str,
+    states: &[String],
+    fields: &[&str],
+) -> Result<Vec<Job>> {
+    let sacct_output = backend.query_jobs(fields, window_start, user)?;
+    let mut jobs = parse_jobs(&sacct_output, fields)?;
+
+    if !states.is_empty() {
+        jobs.retain(|j| states.iter().any(|state| j.state.matches(state)));
+    }
+    Ok(jobs)
+}
+
+/// Opens the history at `history_file`, seeding a new history from the legacy `log_file` next
+/// to it if there is one
+fn open_history(history_file: &Path, legacy_log_file: &Path) -> Result<HistoryStore> {
+    let is_new = !history_file.exists();
+    let mut history = HistoryStore::open(history_file)?;
+    if is_new && legacy_log_file.exists() {
+        history.import_legacy_log(legacy_log_file)?;
+    }
+    Ok(history)
+}
+
+/// Records `jobs` in the history. Nothing is written until the history is saved. Array tasks that
+/// haven't started yet are left out, they're recorded one by one once they have.
+fn log_jobs(jobs: &[Job], history: &mut HistoryStore) {
+    for job in jobs.iter().filter(|job| job.pending_tasks.is_none()) {
+        history.upsert(job);
+    }
+}
+
+/// Whether `job` is the job, array task(s), heterogeneous job component or whole array or
+/// heterogeneous job that `jobid` refers to
+fn matches_jobid(job: &Job, jobid: &str) -> bool {
+    match check_job(jobid) {
+        ParsedJobId::Singular(id) => job.jobid_base == id,
+        ParsedJobId::Array { base, index } => {
+            job.jobid_base == base && job.array_index == Some(index)
+        }
+        ParsedJobId::PendingArray { base, indices, .. } => {
+            job.jobid_base == base && job.array_index.is_some_and(|index| indices.contains(index))
         }
+        ParsedJobId::HetComponent { leader, offset } => {
+            job.jobid_base == leader && job.het_offset == Some(offset)
+        }
+        ParsedJobId::Step { .. } | ParsedJobId::NotJob => false,
+    }
+}
+
+/// Marks the displayable `jobs` matching any of `jobids` (or all of them if `jobids` is empty)
+/// as acknowledged. Returns how many were acknowledged and the IDs that matched nothing.
+fn acknowledge_jobs<'a>(
+    history: &mut HistoryStore,
+    jobs: &[Job],
+    jobids: &'a [String],
+) -> (usize, Vec<&'a str>) {
+    let displayable: Vec<_> = jobs.iter().filter(|job| job.is_displayable()).collect();
+    let selected: Vec<_> = displayable
+        .iter()
+        .filter(|job| jobids.is_empty() || jobids.iter().any(|id| matches_jobid(job, id)))
+        .collect();
+    for job in &selected {
+        history.acknowledge(job);
     }
 
-    job_messages
+    let unmatched = jobids
+        .iter()
+        .filter(|id| !displayable.iter().any(|job| matches_jobid(job, id)))
+        .map(String::as_str)
+        .collect();
+    (selected.len(), unmatched)
 }
 
-fn log_jobs(jobs: &[Job], log_file: &Path) -> Result<()> {
-    let mut fd = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(log_file)
-        .context("Failed to open log file")?;
-    for job in jobs {
-        writeln!(
-            fd,
-            "{};{};{};{};{:?};{:?};{}",
-            job.jobid_display(),
-            job.jobname,
-            job.alloccpus,
-            job.elapsed,
-            job.start,
-            job.end,
-            job.state
-        )?;
+/// Version of the `date_file` format written by [`save_date`]. Version 0 was a bare timestamp in
+/// `LOG_DATE_FORMAT`.
+const SESSION_VERSION: u32 = 1;
+
+/// Contents of `date_file`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SessionRecord {
+    version: u32,
+    last_session: NaiveDateTime,
+}
+
+fn save_date(date_file: &Path, now: NaiveDateTime) -> Result<()> {
+    let record = SessionRecord {
+        version: SESSION_VERSION,
+        last_session: now,
+    };
+    let contents = serde_json::to_string(&record).context("unable to serialize session")?;
+    state::write_atomically(date_file, contents.as_bytes())
+        .context("unable to write date to date_file")
+}
+
+/// Parses the contents of `date_file`, in the current or any older format
+fn parse_session(contents: &str) -> Result<NaiveDateTime> {
+    let contents = contents.trim();
+    if let Ok(date) = NaiveDateTime::parse_from_str(contents, LOG_DATE_FORMAT) {
+        return Ok(date);
     }
-    Ok(())
+    let record: SessionRecord = serde_json::from_str(contents).context("unrecognized format")?;
+    if record.version > SESSION_VERSION {
+        return Err(anyhow!(
+            "written by a newer version of jobs_done (format version {})",
+            record.version
+        ));
+    }
+    Ok(record.last_session)
 }
 
-fn save_date(date_file: &Path) -> Result<()> {
-    let mut fd = File::create(date_file).context("unable to open date_file")?;
-    write!(fd, "{}", Local::now().naive_local().format(LOG_DATE_FORMAT))
-        .context("unable to write date to date_file")?;
-    Ok(())
+/// Start of the previous session. Without a `date_file` that's the start of today; if the file
+/// is empty or can't be parsed (e.g. after a crash), it warns and falls back to the last 24
+/// hours.
+fn get_last_session(date_file: &Path, now: NaiveDateTime) -> Result<NaiveDateTime> {
+    let contents = match fs::read_to_string(date_file) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(NaiveDateTime::new(now.date(), NaiveTime::MIN));
+        }
+        Err(err) => return Err(err).context("unable to read date file"),
+    };
+
+    match parse_session(&contents) {
+        Ok(date) => Ok(date),
+        Err(err) => {
+            eprintln!(
+                "{} ignoring unreadable {} ({err:#}), showing the last 24 hours instead",
+                "warning:".yellow().bold(),
+                date_file.display()
+            );
+            Ok(now - chrono::Duration::days(1))
+        }
+    }
 }
 
-fn get_last_session(date_file: &Path) -> Result<NaiveDateTime> {
-    if date_file.exists() {
-        let contents = fs::read_to_string(date_file).context("unable to read date file")?;
-        if contents.is_empty() {
-            let mut file = File::create(date_file).context("unable to create new prev_job")?;
-            let now = Local::now().naive_local();
-            write!(file, "{}", now.format(LOG_DATE_FORMAT))
-                .context("unable to write date to empty date_file")?;
-            Ok(now)
-        } else {
-            NaiveDateTime::parse_from_str(&contents, LOG_DATE_FORMAT)
-                .context("unable to parse date from date_file")
+/// Prints `jobs` in the requested format. `heading` and `empty_heading` are only shown in the
+/// human-readable table, depending on whether there is anything to list.
+fn print_jobs(
+    output: OutputFormat,
+    columns: &[Column],
+    jobs: &[Job],
+    details: &Details,
+    options: &TableOptions,
+    heading: &str,
+    empty_heading: &str,
+) -> Result<()> {
+    let displayable = jobs.iter().filter(|job| options.lists(job));
+    match output {
+        OutputFormat::Table => print_table(jobs, columns, details, options, heading, empty_heading),
+        OutputFormat::Json => println!("{}", output::render_json(displayable)?),
+        OutputFormat::Ndjson => {
+            for line in output::render_ndjson(displayable)? {
+                println!("{line}");
+            }
         }
+        OutputFormat::Csv => print_delimited(displayable, columns, ','),
+        OutputFormat::Tsv => print_delimited(displayable, columns, '\t'),
+    }
+    Ok(())
+}
+
+fn print_table(
+    jobs: &[Job],
+    columns: &[Column],
+    details: &Details,
+    options: &TableOptions,
+    heading: &str,
+    empty_heading: &str,
+) {
+    let table = create_print(jobs, columns, details, options, None);
+
+    if table.is_empty() {
+        println!("{empty_heading}");
     } else {
-        let now = Local::now().naive_local();
-        Ok(NaiveDateTime::new(
-            now.date(),
-            NaiveTime::from_hms_milli_opt(0, 0, 0, 0).unwrap(),
-        ))
+        println!("{heading}");
+        for line in table.render(table::terminal_width()) {
+            println!("{line}");
+        }
+    }
+
+    if options.show_skipped {
+        return;
+    }
+    for section in Section::ALL
+        .into_iter()
+        .filter(|section| options.include.contains(section))
+    {
+        let table = create_print(jobs, columns, details, options, Some(section));
+        if table.is_empty() {
+            continue;
+        }
+        println!("\n{}", section.heading().bold().underline());
+        for line in table.render(table::terminal_width()) {
+            println!("{line}");
+        }
+    }
+}
+
+/// Lists the jobs in the local history matching `filter`
+fn show_history(
+    history: &HistoryStore,
+    filter: &HistoryFilter,
+    output: OutputFormat,
+    columns: &[Column],
+    options: &TableOptions,
+) -> Result<()> {
+    let jobs: Vec<_> = history.jobs().filter(|job| filter.matches(job)).collect();
+
+    let since = match filter.since {
+        Some(since) => format!(
+            " since {}",
+            since.format(START_END_FORMAT).to_string().yellow()
+        ),
+        None => String::new(),
+    };
+    print_jobs(
+        output,
+        columns,
+        &jobs,
+        &Details::new(),
+        options,
+        &format!("{}{since}", "Jobs in history".bold().underline()),
+        &format!(
+            "{}{since}",
+            "No matching jobs in history".bold().underline()
+        ),
+    )
+}
+
+fn print_delimited<'a>(
+    jobs: impl IntoIterator<Item = &'a Job>,
+    columns: &[Column],
+    delimiter: char,
+) {
+    for row in output::render_delimited(jobs, columns, delimiter) {
+        println!("{row}");
     }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let backend: Box<dyn Backend> = match args.replay.as_deref() {
+        Some(dir) => Box::new(Replay::new(dir)),
+        None => Box::new(Sacct),
+    };
 
     if let Some(jobid) = args.workdir.as_deref() {
-        let workdir_job = get_workdir(jobid)?;
+        let workdir_job = get_workdir(backend.as_ref(), jobid)?;
         println!("workdir: {workdir_job}");
         return Ok(());
     }
 
-    let mut log_file = std::env::current_exe().context("could not acquire log file")?;
-    log_file.pop();
-    log_file.push("log_file");
+    let mut columns: Vec<_> = args.columns.iter().map(|name| Column::new(name)).collect();
+    let mut added_columns = Vec::new();
+    if args.efficiency {
+        added_columns.extend(columns::EFFICIENCY_COLUMNS);
+    }
+    if args.steps {
+        added_columns.extend(columns::STEP_COLUMNS);
+    }
+    for name in added_columns {
+        if !columns.iter().any(|column| column.name == name) {
+            columns.push(Column::new(name));
+        }
+    }
+    let table_options = TableOptions {
+        expand_arrays: args.expand_arrays,
+        steps: args.steps,
+        show_skipped: !args.state.is_empty(),
+        include: if args.all {
+            Section::ALL.to_vec()
+        } else {
+            args.include.clone()
+        },
+    };
+    if let Some(Commands::Wait {
+        jobids,
+        timeout,
+        interval,
+    }) = &args.command
+    {
+        let code = wait::run(
+            backend.as_ref(),
+            jobids,
+            &columns,
+            args.output,
+            // every job waited for is shown, whatever its state
+            &TableOptions {
+                show_skipped: true,
+                ..table_options
+            },
+            std::time::Duration::from_secs(*interval),
+            timeout.map(std::time::Duration::from_secs),
+        )?;
+        std::process::exit(code);
+    }
+
+    let state_dir = StateDir::resolve(args.state_dir.as_deref(), &args.user)?;
+    // older versions kept a single, user-agnostic session next to the executable
+    if std::env::var("USER").is_ok_and(|user| user == args.user) {
+        let mut old_dir = std::env::current_exe().context("could not locate executable")?;
+        old_dir.pop();
+        if state_dir.migrate_from(&old_dir)? {
+            eprintln!("Migrated session state from {}", old_dir.display());
+        }
+    }
+    let history_file = state_dir.history_file();
+    let legacy_log_file = state_dir.legacy_log_file();
+    let date_file = state_dir.date_file();
+
+    if let Some(Commands::History { name, since }) = &args.command {
+        let history = open_history(&history_file, &legacy_log_file)?;
+        let filter = HistoryFilter {
+            name: name.clone(),
+            states: args.state.clone(),
+            since: since.as_deref().map(parse_since).transpose()?,
+        };
+        return show_history(&history, &filter, args.output, &columns, &table_options);
+    }
+
+    if let Some(Commands::Resubmit {
+        jobids,
+        since,
+        dry_run,
+        time,
+        mem,
+    }) = &args.command
+    {
+        let mut jobs = resubmit::select_jobs(
+            backend.as_ref(),
+            jobids,
+            since.as_deref().map(parse_since).transpose()?,
+            &args.user,
+            &args.state,
+            &FORMAT_CMD,
+        )?;
+        let overrides = resubmit::overrides(time.as_deref(), mem.as_deref());
+        // jobs picked by time and state are only repeated once, jobs picked by ID every time
+        if jobids.is_empty() {
+            resubmit::skip_resubmitted(&state_dir, &mut jobs)?;
+        }
+        return resubmit::run(backend.as_ref(), &state_dir, &jobs, &overrides, *dry_run);
+    }
 
-    let mut date_file = std::env::current_exe().context("could not acquire date file")?;
-    date_file.pop();
-    date_file.push("date_file");
+    if let Some(Commands::ResubmitArray {
+        jobid,
+        dry_run,
+        time,
+        mem,
+    }) = &args.command
+    {
+        let overrides = resubmit::overrides(time.as_deref(), mem.as_deref());
+        return resubmit::run_array(
+            backend.as_ref(),
+            &state_dir,
+            jobid,
+            &args.state,
+            &overrides,
+            *dry_run,
+        );
+    }
+
+    let mut webhook = args
+        .webhook
+        .as_deref()
+        .map(|url| Webhook::new(url, args.webhook_template.as_deref()));
+    if let Some(Commands::Watch { interval }) = &args.command {
+        return watch::run(
+            backend.as_ref(),
+            &state_dir,
+            &args.user,
+            &args.state,
+            &columns,
+            args.output,
+            std::time::Duration::from_secs(*interval),
+            &args.hook,
+            webhook.as_mut(),
+        );
+    }
 
-    let last_session = get_last_session(&date_file)?;
+    let unseen = args.unseen || args.peek || matches!(args.command, Some(Commands::Ack { .. }));
     let now = Local::now().naive_local();
+    let last_session = get_last_session(&date_file, now)?;
     let window_start = if let Some(since) = args.since.as_deref() {
-        NaiveDateTime::parse_from_str(since, INPUT_DATE_FORMAT)
-            .context("unable to parse --since with expected format")?
+        parse_since(since)?
     } else if let Some(hours) = args.hours {
         now - chrono::Duration::hours(hours)
     } else if let Some(days) = args.days {
         now - chrono::Duration::days(days)
     } else if args.day {
         now - chrono::Duration::days(1)
+    } else if unseen {
+        // seen jobs are recognized by ID, so the window can safely reach further back than the
+        // last session in case the clocks disagree
+        last_session - UNSEEN_OVERLAP
     } else {
         last_session
     };
-    let formatted_window_start = window_start.format(START_END_FORMAT).to_string().yellow();
+    let collect = if table_options.include.contains(&Section::Running) {
+        collect_all_jobs
+    } else {
+        collect_jobs
+    };
+    let mut jobs = collect(
+        backend.as_ref(),
+        window_start,
+        &args.user,
+        &args.state,
+        &columns::sacct_fields(&columns),
+    )?;
 
-    let sacct_output = call_sacct(FORMAT_CMD, window_start, &args.user)?;
-    let mut jobs = get_finished_jobs(&sacct_output)?;
+    // other login shells may be updating the same state right now, so the lock is only held
+    // while the history is read and written, never while printing
+    let lock = state_dir.lock()?;
+    let mut history = open_history(&history_file, &legacy_log_file)?;
+    log_jobs(&jobs, &mut history);
 
-    if !args.state.is_empty() {
-        let states: Vec<_> = args.state.iter().map(|s| s.to_uppercase()).collect();
-        jobs.retain(|j| states.contains(&j.state));
+    if let Some(Commands::Ack { jobids }) = &args.command {
+        let (acknowledged, unmatched) = acknowledge_jobs(&mut history, &jobs, jobids);
+        history.save()?;
+        drop(lock);
+        for jobid in unmatched {
+            eprintln!(
+                "{} no finished job {jobid} found since {}",
+                "warning:".yellow().bold(),
+                window_start.format(START_END_FORMAT)
+            );
+        }
+        println!("Marked {acknowledged} job(s) as seen");
+        return Ok(());
     }
 
-    let job_messages = create_print(&jobs);
+    if unseen {
+        jobs.retain(|job| !history.is_acknowledged(job));
+    }
+    history.save()?;
+    drop(lock);
 
-    if job_messages.is_empty() {
-        println!(
-            "{} {}",
-            "No jobs have finished since".bold().underline(),
-            formatted_window_start
-        );
+    let formatted_window_start = window_start.format(START_END_FORMAT).to_string().yellow();
+    let (heading, empty_heading) = if unseen {
+        (
+            "Unseen jobs completed since:",
+            "No unseen jobs have finished since",
+        )
     } else {
-        println!(
-            "{} {}",
-            "Jobs completed since:".bold().underline(),
-            formatted_window_start
-        );
-        let headers: String = HEADERS.iter().map(|h| h.bold().to_string()).collect();
-        println!("{headers}");
-
-        for job in job_messages {
-            println!("{job}");
+        ("Jobs completed since:", "No jobs have finished since")
+    };
+    let mut details = Details::new();
+    // the tails are only shown in the table
+    if let Some(lines) = args.tail.filter(|_| args.output == OutputFormat::Table) {
+        for job in jobs
+            .iter()
+            .filter(|job| table_options.lists(job) && job.state.category() == Category::Failure)
+        {
+            let tail = logs::tail_job(backend.as_ref(), job, &args.user, lines);
+            details.insert(job.jobid_display(), tail);
         }
     }
+    print_jobs(
+        args.output,
+        &columns,
+        &jobs,
+        &details,
+        &table_options,
+        &format!("{} {formatted_window_start}", heading.bold().underline()),
+        &format!(
+            "{} {formatted_window_start}",
+            empty_heading.bold().underline()
+        ),
+    )?;
 
-    log_jobs(&jobs, &log_file)?;
-    save_date(&date_file)?;
+    let lock = state_dir.lock()?;
+    if unseen && !args.peek {
+        let mut history = open_history(&history_file, &legacy_log_file)?;
+        acknowledge_jobs(&mut history, &jobs, &[]);
+        history.save()?;
+    }
+    save_date(&date_file, now)?;
+    drop(lock);
+    hooks::run(backend.as_ref(), &state_dir, &args.hook, &jobs)?;
+    if let Some(webhook) = webhook.as_mut() {
+        notify::notify(webhook, &state_dir, &jobs)?;
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::replay_fixtures;
 
     macro_rules! jobtypes_tests {
         ($($name:ident: $value:expr,)*) => {
@@ -437,16 +1507,26 @@ mod tests {
     }
     jobtypes_tests! {
         check_job0: ("39122024_15+", ParsedJobId::NotJob),
-        check_job1: ("39122024_3.1", ParsedJobId::NotJob),
-        check_job2: ("39122024.1", ParsedJobId::NotJob),
+        check_job1: ("39122024_3.1", ParsedJobId::Step { base: 39122024, index: Some(3) }),
+        check_job2: ("39122024.1", ParsedJobId::Step { base: 39122024, index: None }),
         check_job3: ("39122024.1+", ParsedJobId::NotJob),
         check_job4: ("39122024_16+", ParsedJobId::NotJob),
         check_job5: ("39122024_16", ParsedJobId::Array { base: 39122024, index: 16 }),
         check_job6: ("39122024", ParsedJobId::Singular(39122024)),
-        check_job7: ("56938944_10.batch", ParsedJobId::NotJob),
-        check_job8: ("56938944_10.extern", ParsedJobId::NotJob),
+        check_job7: ("56938944_10.batch", ParsedJobId::Step { base: 56938944, index: Some(10) }),
+        check_job8: ("56938944_10.extern", ParsedJobId::Step { base: 56938944, index: Some(10) }),
         check_job9: ("56938944_10", ParsedJobId::Array { base: 56938944, index: 10 }),
-        check_job10: ("56938942.batch", ParsedJobId::NotJob),
+        check_job10: ("56938942.batch", ParsedJobId::Step { base: 56938942, index: None }),
+        check_job11: ("56938942.", ParsedJobId::NotJob),
+        check_job12: ("batch.56938942", ParsedJobId::NotJob),
+        check_job13: ("123_[1-5]", ParsedJobId::PendingArray { base: 123, indices: (1..=5).collect(), max_running: None }),
+        check_job14: ("123_[1-1000%50]", ParsedJobId::PendingArray { base: 123, indices: (1..=1000).collect(), max_running: Some(50) }),
+        check_job15: ("123_[2,4-5]", ParsedJobId::PendingArray { base: 123, indices: [2, 4, 5].into_iter().collect(), max_running: None }),
+        check_job16: ("123_[1-5%x]", ParsedJobId::NotJob),
+        check_job17: ("123_[]", ParsedJobId::NotJob),
+        check_job18: ("123+0", ParsedJobId::HetComponent { leader: 123, offset: 0 }),
+        check_job19: ("123+1.batch", ParsedJobId::Step { base: 123, index: None }),
+        check_job20: ("123+", ParsedJobId::NotJob),
     }
 
     macro_rules! parse_job_tests {
@@ -455,7 +1535,8 @@ mod tests {
             #[test]
             fn $name() {
                 let (jobid_base, array_index, input, expected) = $value;
-                let job = Job::parse_job(jobid_base, array_index, &input, INPUT_DATE_FORMAT).unwrap();
+                let record = SacctRecord::parse(&FORMAT_CMD, input).unwrap();
+                let job = Job::parse_job(jobid_base, array_index, &record, INPUT_DATE_FORMAT).unwrap();
                 assert_eq!(expected.jobid_base, job.jobid_base);
                 assert_eq!(expected.array_index, job.array_index);
                 assert_eq!(expected.jobname, job.jobname);
@@ -464,14 +1545,43 @@ mod tests {
                 assert_eq!(expected.start, job.start);
                 assert_eq!(expected.end, job.end);
                 assert_eq!(expected.state, job.state);
+                assert_eq!(expected.extra, job.extra);
+            }
+    )*
+        }
+    }
+    macro_rules! parse_duration_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (input, expected) = $value;
+                assert_eq!(expected, parse_duration(input));
             }
     )*
         }
     }
+    parse_duration_tests! {
+        parse_duration0: ("00:08:58", Some(538)),
+        parse_duration1: ("1-02:03:04", Some(93784)),
+        parse_duration2: ("12:34.567", Some(754)),
+        parse_duration3: ("00:00:00", Some(0)),
+        parse_duration4: ("", None),
+        parse_duration5: ("Unknown", None),
+        parse_duration6: ("1:2:3:4", None),
+    }
+
+    #[test]
+    fn format_duration_round_trips() {
+        for value in ["00:08:58", "1-02:03:04", "00:00:00", "100-00:00:01"] {
+            assert_eq!(format_duration(parse_duration(value).unwrap()), value);
+        }
+    }
+
     parse_job_tests! {
         parse_job0: (
             39139726_usize, None,
-            ["39139726", "1e-2", "84", "00:08:58", "2023-04-22T16:15:05", "2023-04-22T16:24:03", "COMPLETED"],
+            "39139726|1e-2|84|00:08:58|2023-04-22T16:15:05|2023-04-22T16:24:03|COMPLETED",
             Job{
                 jobid_base: 39139726,
                 array_index: None,
@@ -480,12 +1590,13 @@ mod tests {
                 elapsed: "00:08:58".to_string(),
                 start: Some(NaiveDateTime::parse_from_str("2023-04-22T16:15:05", INPUT_DATE_FORMAT).unwrap()),
                 end: Some(NaiveDateTime::parse_from_str("2023-04-22T16:24:03", INPUT_DATE_FORMAT).unwrap()),
-                state: "COMPLETED".to_string()
+                state: JobState::Completed,
+                ..Default::default()
             }
         ),
         parse_job1: (
             50280159_usize, None,
-            ["50280159", "MultiprocessDistances", "4", "20:27:32", "2025-03-19T19:32:54", "Unknown", "FAILED"],
+            "50280159|MultiprocessDistances|4|20:27:32|2025-03-19T19:32:54|Unknown|FAILED",
             Job{
                 jobid_base: 50280159,
                 array_index: None,
@@ -494,12 +1605,13 @@ mod tests {
                 elapsed: "20:27:32".to_string(),
                 start: Some(NaiveDateTime::parse_from_str("2025-03-19T19:32:54", INPUT_DATE_FORMAT).unwrap()),
                 end: None,
-                state: "FAILED".to_string()
+                state: JobState::Failed,
+                ..Default::default()
             }
         ),
         parse_job_array: (
             56938944_usize, Some(3_usize),
-            ["56938944_3", "2JobArray", "2", "00:01:00", "2023-04-22T16:15:05", "2023-04-22T16:16:05", "COMPLETED"],
+            "56938944_3|2JobArray|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED",
             Job{
                 jobid_base: 56938944,
                 array_index: Some(3),
@@ -508,26 +1620,58 @@ mod tests {
                 elapsed: "00:01:00".to_string(),
                 start: Some(NaiveDateTime::parse_from_str("2023-04-22T16:15:05", INPUT_DATE_FORMAT).unwrap()),
                 end: Some(NaiveDateTime::parse_from_str("2023-04-22T16:16:05", INPUT_DATE_FORMAT).unwrap()),
-                state: "COMPLETED".to_string()
+                state: JobState::Completed,
+                ..Default::default()
+            }
+        ),
+        parse_job_name_with_spaces: (
+            56938945_usize, None,
+            "56938945|relax slab 2x2 (restart)|16|00:10:00|2023-04-22T16:15:05|2023-04-22T16:25:05|COMPLETED",
+            Job{
+                jobid_base: 56938945,
+                array_index: None,
+                jobname: "relax slab 2x2 (restart)".to_string(),
+                alloccpus: 16,
+                elapsed: "00:10:00".to_string(),
+                start: Some(NaiveDateTime::parse_from_str("2023-04-22T16:15:05", INPUT_DATE_FORMAT).unwrap()),
+                end: Some(NaiveDateTime::parse_from_str("2023-04-22T16:25:05", INPUT_DATE_FORMAT).unwrap()),
+                state: JobState::Completed,
+                ..Default::default()
+            }
+        ),
+        parse_job_blank_dates: (
+            56938946_usize, None,
+            "56938946|NeverRan|0|00:00:00|||FAILED",
+            Job{
+                jobid_base: 56938946,
+                array_index: None,
+                jobname: "NeverRan".to_string(),
+                alloccpus: 0,
+                elapsed: "00:00:00".to_string(),
+                start: None,
+                end: None,
+                state: JobState::Failed,
+                ..Default::default()
             }
         ),
     }
 
     #[test]
     fn get_finished_jobs_with_arrays() {
-        // Simulates the new sacct output format (jobid, jobname, alloccpus, elapsed, start, end, state)
+        // Simulates the sacct -P output format (jobid, jobname, alloccpus, elapsed, start, end, state)
         let sacct_output = "\
-            56938942 SingularJob 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938942.batch batch 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938942.extern extern 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938944_1 ArrayJob 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938944_1.batch batch 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938944_1.extern extern 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938944_2 ArrayJob 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938944_2.batch batch 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938944_2.extern extern 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED";
-
-        let jobs = get_finished_jobs(sacct_output).unwrap();
+56938942|SingularJob|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938942.batch|batch|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938942.extern|extern|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938944_1|ArrayJob|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938944_1.batch|batch|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938944_1.extern|extern|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938944_2|ArrayJob|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938944_2.batch|batch|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938944_2.extern|extern|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+";
+
+        let jobs = get_finished_jobs(sacct_output, &FORMAT_CMD).unwrap();
         assert_eq!(jobs.len(), 3);
 
         // First job: singular
@@ -556,7 +1700,8 @@ mod tests {
             elapsed: "00:00:01".to_string(),
             start: None,
             end: None,
-            state: "COMPLETED".to_string(),
+            state: JobState::Completed,
+            ..Default::default()
         };
         assert_eq!(singular.jobid_display(), "12345678");
 
@@ -568,7 +1713,8 @@ mod tests {
             elapsed: "00:00:01".to_string(),
             start: None,
             end: None,
-            state: "COMPLETED".to_string(),
+            state: JobState::Completed,
+            ..Default::default()
         };
         assert_eq!(array.jobid_display(), "12345678_10");
     }
@@ -576,50 +1722,527 @@ mod tests {
     #[test]
     fn filter_jobs_by_state() {
         let sacct_output = "\
-            56938942 CompletedJob 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938942.batch batch 2 00:01:00 2023-04-22T16:15:05 2023-04-22T16:16:05 COMPLETED \
-            56938943 FailedJob 4 00:05:00 2023-04-22T16:15:05 2023-04-22T16:20:05 FAILED \
-            56938943.batch batch 4 00:05:00 2023-04-22T16:15:05 2023-04-22T16:20:05 FAILED \
-            56938944 TimedOutJob 8 01:00:00 2023-04-22T16:15:05 2023-04-22T17:15:05 TIMEOUT \
-            56938944.batch batch 8 01:00:00 2023-04-22T16:15:05 2023-04-22T17:15:05 TIMEOUT";
-
-        let jobs = get_finished_jobs(sacct_output).unwrap();
+56938942|CompletedJob|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938942.batch|batch|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938943|FailedJob|4|00:05:00|2023-04-22T16:15:05|2023-04-22T16:20:05|FAILED
+56938943.batch|batch|4|00:05:00|2023-04-22T16:15:05|2023-04-22T16:20:05|FAILED
+56938944|TimedOutJob|8|01:00:00|2023-04-22T16:15:05|2023-04-22T17:15:05|TIMEOUT
+56938944.batch|batch|8|01:00:00|2023-04-22T16:15:05|2023-04-22T17:15:05|TIMEOUT
+";
+
+        let jobs = get_finished_jobs(sacct_output, &FORMAT_CMD).unwrap();
         assert_eq!(jobs.len(), 3);
 
         // Filter to only FAILED
-        let states = vec!["FAILED".to_string()];
+        let states = ["FAILED".to_string()];
         let filtered: Vec<_> = jobs
             .into_iter()
-            .filter(|j| states.contains(&j.state))
+            .filter(|j| states.iter().any(|s| j.state.matches(s)))
             .collect();
         assert_eq!(filtered.len(), 1);
         assert_eq!(filtered[0].jobname, "FailedJob");
-        assert_eq!(filtered[0].state, "FAILED");
+        assert_eq!(filtered[0].state, JobState::Failed);
 
-        // Filter with case-insensitive input (uppercase normalization)
-        let jobs = get_finished_jobs(sacct_output).unwrap();
-        let states: Vec<String> = vec!["failed".to_string(), "timeout".to_string()]
-            .iter()
-            .map(|s| s.to_uppercase())
-            .collect();
+        // Filter with case-insensitive input and short codes
+        let jobs = get_finished_jobs(sacct_output, &FORMAT_CMD).unwrap();
+        let states = ["failed".to_string(), "TO".to_string()];
         let filtered: Vec<_> = jobs
             .into_iter()
-            .filter(|j| states.contains(&j.state))
+            .filter(|j| states.iter().any(|s| j.state.matches(s)))
             .collect();
         assert_eq!(filtered.len(), 2);
-        assert_eq!(filtered[0].state, "FAILED");
-        assert_eq!(filtered[1].state, "TIMEOUT");
+        assert_eq!(filtered[0].state, JobState::Failed);
+        assert_eq!(filtered[1].state, JobState::Timeout);
 
         // Empty filter shows all
-        let jobs = get_finished_jobs(sacct_output).unwrap();
+        let jobs = get_finished_jobs(sacct_output, &FORMAT_CMD).unwrap();
         let states: Vec<String> = Vec::new();
         let filtered: Vec<_> = if states.is_empty() {
             jobs
         } else {
             jobs.into_iter()
-                .filter(|j| states.contains(&j.state))
+                .filter(|j| states.iter().any(|s| j.state.matches(s)))
                 .collect()
         };
         assert_eq!(filtered.len(), 3);
     }
+
+    #[test]
+    fn cancelled_jobs_are_skipped_unless_asked_for() {
+        let sacct_output = "\
+56938960|ByMe|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|CANCELLED by 1234
+56938961|Cut|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|CANCELLED+
+56938962|Done|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+";
+        let jobs = get_finished_jobs(sacct_output, &FORMAT_CMD).unwrap();
+        assert_eq!(jobs[0].state, JobState::Cancelled { by: Some(1234) });
+        assert!(jobs.iter().all(Job::is_finished));
+        let shown: Vec<_> = jobs.iter().filter(|job| job.is_displayable()).collect();
+        assert_eq!(shown.len(), 1);
+
+        let options = TableOptions {
+            show_skipped: true,
+            ..TableOptions::default()
+        };
+        let lines =
+            create_print(&jobs, &default_columns(), &Details::new(), &options, None).render(None);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].contains("CANCELLED by 1234"));
+    }
+
+    #[test]
+    fn get_finished_jobs_keeps_alignment_after_odd_records() {
+        // A job name with spaces and a blank end field must not shift the records after them
+        let sacct_output = "\
+56938950|my job with spaces|2|00:01:00|2023-04-22T16:15:05||FAILED
+56938951|NextJob|4|00:02:00|2023-04-22T16:15:05|2023-04-22T16:17:05|COMPLETED
+";
+        let jobs = get_finished_jobs(sacct_output, &FORMAT_CMD).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].jobname, "my job with spaces");
+        assert_eq!(jobs[0].end, None);
+        assert_eq!(jobs[1].jobid_base, 56938951);
+        assert_eq!(jobs[1].jobname, "NextJob");
+        assert_eq!(jobs[1].alloccpus, 4);
+        assert_eq!(jobs[1].state, JobState::Completed);
+    }
+
+    #[test]
+    fn get_finished_jobs_skips_malformed_line() {
+        let sacct_output = "\
+56938950|GoodJob|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938951|BadJob|4|00:02:00
+56938952|NextJob|x|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938953|LastJob|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+";
+        let jobs = get_finished_jobs(sacct_output, &FORMAT_CMD).unwrap();
+        let names: Vec<_> = jobs.iter().map(|job| job.jobname.as_str()).collect();
+        assert_eq!(names, ["GoodJob", "LastJob"]);
+    }
+
+    #[test]
+    fn delimiters_in_job_names_are_kept() {
+        let sacct_output = "\
+56938950|a|b||c|2|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED
+56938951|NextJob|4|00:02:00|2023-04-22T16:15:05|2023-04-22T16:17:05|FAILED
+";
+        let jobs = get_finished_jobs(sacct_output, &FORMAT_CMD).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].jobname, "a|b||c");
+        assert_eq!(jobs[0].alloccpus, 2);
+        assert_eq!(jobs[0].state, JobState::Completed);
+        assert_eq!(jobs[1].jobname, "NextJob");
+
+        // with two free-text fields there's no telling which one has the delimiter
+        let fields = [&FORMAT_CMD[..], &["workdir"]].concat();
+        let record = SacctRecord::parse(&fields, "1|a|b|1|00:01:00|||COMPLETED|/w");
+        assert!(record.is_err());
+    }
+
+    fn default_columns() -> Vec<Column> {
+        FORMAT_CMD.into_iter().map(Column::new).collect()
+    }
+
+    #[test]
+    fn collect_and_print_from_replay() {
+        let window_start = Local::now().naive_local();
+        let jobs = collect_jobs(&replay_fixtures(), window_start, "me", &[], &FORMAT_CMD).unwrap();
+        let ids: Vec<_> = jobs.iter().map(Job::jobid_display).collect();
+        // RUNNING jobs are never collected, PENDING ones are hidden when printing
+        assert_eq!(
+            ids,
+            [
+                "56938942",
+                "56938943",
+                "56938944_1",
+                "56938944_2",
+                "56938946"
+            ]
+        );
+        assert_eq!(jobs[1].jobname, "relax slab 2x2");
+
+        // a header and five rows
+        let lines = create_print(
+            &jobs,
+            &default_columns(),
+            &Details::new(),
+            &TableOptions::default(),
+            None,
+        )
+        .render(None);
+        assert_eq!(lines.len(), 6);
+        assert!(lines[3].starts_with("56938944 "));
+        assert!(lines[4].starts_with("  1 "));
+        assert!(lines[5].starts_with("  2 "));
+    }
+
+    #[test]
+    fn large_arrays_are_collapsed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sacct = String::from("JobID|JobName|AllocCPUS|Elapsed|Start|End|State\n");
+        for index in 1..=12 {
+            let state = if index == 5 { "FAILED" } else { "COMPLETED" };
+            sacct.push_str(&format!(
+                "77_{index}|scan|1|00:{index:02}:00|2023-04-22T16:15:05|2023-04-22T16:30:05|{state}\n"
+            ));
+        }
+        std::fs::write(dir.path().join("jobs.sacct"), sacct).unwrap();
+        let window_start = Local::now().naive_local();
+        let jobs = collect_jobs(
+            &Replay::new(dir.path()),
+            window_start,
+            "me",
+            &[],
+            &FORMAT_CMD,
+        )
+        .unwrap();
+
+        // a header, the array row and its summary
+        let lines = create_print(
+            &jobs,
+            &default_columns(),
+            &Details::new(),
+            &TableOptions::default(),
+            None,
+        )
+        .render(None);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("77 "));
+        assert!(lines[2].trim_start().starts_with("12 tasks: "));
+        assert!(lines[2].contains("COMPLETED 1-4,6-12"));
+        assert!(lines[2].contains("FAILED 5"));
+        assert_eq!(
+            lines[3].trim(),
+            "elapsed: min 00:01:00, median 00:06:00, max 00:12:00"
+        );
+
+        let expanded = TableOptions {
+            expand_arrays: true,
+            ..TableOptions::default()
+        };
+        let lines =
+            create_print(&jobs, &default_columns(), &Details::new(), &expanded, None).render(None);
+        assert_eq!(lines.len(), 14);
+        assert!(lines[13].starts_with("  12 "));
+
+        // details, e.g. from --tail, are shown under the row of their task
+        let mut details = Details::new();
+        details.insert("77_5".to_string(), vec!["Segmentation fault".to_string()]);
+        let lines = create_print(
+            &jobs,
+            &default_columns(),
+            &details,
+            &TableOptions::default(),
+            None,
+        )
+        .render(None);
+        assert_eq!(lines.len(), 6);
+        assert!(lines[4].starts_with("  5 "));
+        assert!(lines[5].contains("Segmentation fault"));
+    }
+
+    #[test]
+    fn het_jobs_and_pending_arrays() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("jobs.sacct"),
+            "JobID|JobName|AllocCPUS|Elapsed|Start|End|State\n\
+             77_[3-5%2]|scan|1|00:00:00|Unknown|Unknown|PENDING\n\
+             77_1|scan|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED\n\
+             77_2|scan|1|00:02:00|2023-04-22T16:15:05|2023-04-22T16:17:05|FAILED\n\
+             78+0|coupled|4|00:03:00|2023-04-22T16:15:05|2023-04-22T16:18:05|COMPLETED\n\
+             78+0.batch|batch|4|00:03:00|2023-04-22T16:15:05|2023-04-22T16:18:05|COMPLETED\n\
+             78+1|coupled|1|00:03:00|2023-04-22T16:15:05|2023-04-22T16:18:05|COMPLETED\n",
+        )
+        .unwrap();
+        let window_start = Local::now().naive_local();
+        let jobs = collect_jobs(
+            &Replay::new(dir.path()),
+            window_start,
+            "me",
+            &[],
+            &FORMAT_CMD,
+        )
+        .unwrap();
+        let ids: Vec<_> = jobs.iter().map(Job::jobid_display).collect();
+        assert_eq!(ids, ["77_[3-5%2]", "77_1", "77_2", "78+0", "78+1"]);
+        assert!(matches_jobid(&jobs[4], "78+1"));
+        assert!(matches_jobid(&jobs[4], "78"));
+        assert!(!matches_jobid(&jobs[3], "78+1"));
+
+        let lines = create_print(
+            &jobs,
+            &default_columns(),
+            &Details::new(),
+            &TableOptions::default(),
+            None,
+        )
+        .render(None);
+        assert_eq!(lines.len(), 8);
+        assert!(lines[1].starts_with("77 "));
+        assert!(lines[2].starts_with("  1 "));
+        assert!(lines[3].starts_with("  2 "));
+        assert!(lines[4].starts_with("  [3-5%2] "));
+        assert!(lines[4].contains("3 tasks not yet started"));
+        assert!(lines[5].starts_with("78 "));
+        assert!(lines[5].contains("coupled"));
+        assert!(lines[6].starts_with("  +0 "));
+        assert!(lines[7].starts_with("  +1 "));
+    }
+
+    #[test]
+    fn steps_from_replay() {
+        let window_start = Local::now().naive_local();
+        let columns: Vec<_> = ["jobid", "state", "exitcode", "maxrss"]
+            .into_iter()
+            .map(Column::new)
+            .collect();
+        let fields = columns::sacct_fields(&columns);
+        let jobs = collect_jobs(&replay_fixtures(), window_start, "me", &[], &fields).unwrap();
+        let steps: Vec<_> = jobs[3].steps.iter().map(|step| &step.id).collect();
+        assert_eq!(steps, ["batch"]);
+        assert_eq!(jobs[3].steps[0].job.state, JobState::Cancelled { by: None });
+
+        let options = TableOptions {
+            steps: true,
+            ..TableOptions::default()
+        };
+        let lines = create_print(&jobs, &columns, &Details::new(), &options, None).render(None);
+        assert_eq!(lines.len(), 11);
+        assert!(lines[2].starts_with("  .batch "));
+        assert!(lines[3].starts_with("  .extern "));
+        assert!(lines[10].starts_with("    .batch "));
+        assert!(lines[10].contains("CANCELLED"));
+        assert!(lines[10].contains("0:15"));
+        assert!(lines[10].contains("3.7G"));
+    }
+
+    #[test]
+    fn extra_columns_from_replay() {
+        let window_start = Local::now().naive_local();
+        let columns: Vec<_> = ["jobid", "WorkDir", "state"]
+            .into_iter()
+            .map(Column::new)
+            .collect();
+        let fields = columns::sacct_fields(&columns);
+        let jobs = collect_jobs(&replay_fixtures(), window_start, "me", &[], &fields).unwrap();
+        assert_eq!(jobs[1].extra["workdir"], "/home/me/slab");
+
+        // plain text columns are never coloured, only the state is
+        let lines = create_print(
+            &jobs,
+            &columns,
+            &Details::new(),
+            &TableOptions::default(),
+            None,
+        )
+        .render(None);
+        assert!(lines[0].contains("Work Dir"));
+        assert!(lines[2].starts_with("56938943  /home/me/slab      "));
+        assert!(lines[2].contains("FAILED"));
+    }
+
+    #[test]
+    fn efficiency_from_replay() {
+        let window_start = Local::now().naive_local();
+        let columns = default_columns();
+        let fields = columns::sacct_fields(&columns);
+        let jobs = collect_jobs(&replay_fixtures(), window_start, "me", &[], &fields).unwrap();
+        // MaxRSS comes from the batch step, ReqMem per CPU is scaled to the whole job
+        let resources = &jobs[3].resources;
+        assert_eq!(resources.max_rss, Some(3900000 * 1024));
+        assert_eq!(resources.req_mem, Some(2 * 2000 * 1024 * 1024));
+
+        let efficiency = efficiency::Efficiency::of(&jobs[0]);
+        assert_eq!(efficiency.cpu, Some(0.75));
+        assert_eq!(efficiency.time, Some(0.1));
+
+        let columns: Vec<_> = columns::EFFICIENCY_COLUMNS
+            .into_iter()
+            .map(Column::new)
+            .collect();
+        let values: Vec<_> = columns.iter().map(|c| c.plain_value(&jobs[0])).collect();
+        assert_eq!(values, ["75.0%", "25.0%", "10.0%"]);
+    }
+
+    #[test]
+    fn failures_are_explained_from_replay() {
+        let window_start = Local::now().naive_local();
+        let columns: Vec<_> = ["jobid", "exitcode", "reason"]
+            .into_iter()
+            .map(Column::new)
+            .collect();
+        let fields = columns::sacct_fields(&columns);
+        let jobs = collect_jobs(&replay_fixtures(), window_start, "me", &[], &fields).unwrap();
+        let rows: Vec<_> = jobs
+            .iter()
+            .map(|job| {
+                columns
+                    .iter()
+                    .map(|c| c.plain_value(job))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(rows[0], ["56938942", "0:0", ""]);
+        assert_eq!(
+            rows[1],
+            ["56938943", "139:0", "segmentation fault (SIGSEGV)"]
+        );
+        assert_eq!(rows[3], ["56938944_2", "0:0", "reached its time limit"]);
+    }
+
+    #[test]
+    fn collect_jobs_filters_states_from_replay() {
+        let window_start = Local::now().naive_local();
+        let states = ["failed".to_string(), "TIMEOUT".to_string()];
+        let jobs =
+            collect_jobs(&replay_fixtures(), window_start, "me", &states, &FORMAT_CMD).unwrap();
+        let ids: Vec<_> = jobs.iter().map(Job::jobid_display).collect();
+        assert_eq!(ids, ["56938943", "56938944_2"]);
+
+        let states = ["R".to_string()];
+        let jobs =
+            collect_jobs(&replay_fixtures(), window_start, "me", &states, &FORMAT_CMD).unwrap();
+        let ids: Vec<_> = jobs.iter().map(Job::jobid_display).collect();
+        assert_eq!(ids, ["56938945"]);
+    }
+
+    #[test]
+    fn unfinished_jobs_are_listed_in_sections_from_replay() {
+        let window_start = Local::now().naive_local();
+        let columns = default_columns();
+        let fields = columns::sacct_fields(&columns);
+        let jobs = collect_all_jobs(&replay_fixtures(), window_start, "me", &[], &fields).unwrap();
+        let options = TableOptions {
+            include: vec![Section::Running, Section::Pending],
+            ..TableOptions::default()
+        };
+        assert_eq!(jobs.iter().filter(|job| options.lists(job)).count(), 6);
+        assert!(!TableOptions::default().lists(&jobs[4]));
+
+        // the finished jobs are as without --include
+        let finished = create_print(&jobs, &columns, &Details::new(), &options, None).render(None);
+        assert_eq!(finished.len(), 6);
+
+        let running = create_print(
+            &jobs,
+            &columns,
+            &Details::new(),
+            &options,
+            Some(Section::Running),
+        )
+        .render(None);
+        assert_eq!(running.len(), 2);
+        assert!(running[1].starts_with("56938945 "));
+        assert!(running[1].contains("00:30:00 / 02:00:00"));
+
+        let pending = create_print(
+            &jobs,
+            &columns,
+            &Details::new(),
+            &options,
+            Some(Section::Pending),
+        )
+        .render(None);
+        assert_eq!(pending.len(), 2);
+        assert!(pending[1].starts_with("56938946 "));
+    }
+
+    #[test]
+    fn get_workdir_from_replay() {
+        let workdir = get_workdir(&replay_fixtures(), "56938943").unwrap();
+        assert_eq!(workdir, "/home/me/slab");
+        assert!(get_workdir(&replay_fixtures(), "1").is_err());
+    }
+
+    #[test]
+    fn session_round_trips_and_reads_old_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let date_file = dir.path().join("date_file");
+        let now = NaiveDateTime::parse_from_str("2023-04-22T16:15:05", INPUT_DATE_FORMAT).unwrap();
+
+        save_date(&date_file, now).unwrap();
+        assert_eq!(get_last_session(&date_file, now).unwrap(), now);
+
+        fs::write(&date_file, "2023-04-20 08:00:00").unwrap();
+        assert_eq!(
+            get_last_session(&date_file, now).unwrap(),
+            NaiveDateTime::parse_from_str("2023-04-20T08:00:00", INPUT_DATE_FORMAT).unwrap()
+        );
+    }
+
+    #[test]
+    fn missing_session_starts_today() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = NaiveDateTime::parse_from_str("2023-04-22T16:15:05", INPUT_DATE_FORMAT).unwrap();
+        assert_eq!(
+            get_last_session(&dir.path().join("date_file"), now).unwrap(),
+            NaiveDateTime::parse_from_str("2023-04-22T00:00:00", INPUT_DATE_FORMAT).unwrap()
+        );
+    }
+
+    #[test]
+    fn corrupt_session_falls_back_to_last_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let date_file = dir.path().join("date_file");
+        let now = NaiveDateTime::parse_from_str("2023-04-22T16:15:05", INPUT_DATE_FORMAT).unwrap();
+        let day_before = now - chrono::Duration::days(1);
+
+        for contents in [
+            "",
+            "2023-04-2",
+            "{\"version\":1,\"last_ses",
+            "{\"version\":99,\"last_session\":\"2023-04-22T16:15:05\"}",
+        ] {
+            fs::write(&date_file, contents).unwrap();
+            assert_eq!(get_last_session(&date_file, now).unwrap(), day_before);
+        }
+    }
+
+    #[test]
+    fn jobid_matching() {
+        let window_start = Local::now().naive_local();
+        let jobs = collect_jobs(&replay_fixtures(), window_start, "me", &[], &FORMAT_CMD).unwrap();
+        let matching = |jobid: &str| -> Vec<_> {
+            jobs.iter()
+                .filter(|job| matches_jobid(job, jobid))
+                .map(Job::jobid_display)
+                .collect()
+        };
+        assert_eq!(matching("56938942"), ["56938942"]);
+        assert_eq!(matching("56938944"), ["56938944_1", "56938944_2"]);
+        assert_eq!(matching("56938944_2"), ["56938944_2"]);
+        assert!(matching("56938944_3").is_empty());
+        assert!(matching("not-a-job").is_empty());
+    }
+
+    #[test]
+    fn acknowledged_jobs_are_not_unseen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = HistoryStore::open(dir.path().join("history.jsonl")).unwrap();
+        let window_start = Local::now().naive_local();
+        let jobs = collect_jobs(&replay_fixtures(), window_start, "me", &[], &FORMAT_CMD).unwrap();
+        log_jobs(&jobs, &mut history);
+
+        let jobids = ["56938944".to_string(), "123".to_string()];
+        let (acknowledged, unmatched) = acknowledge_jobs(&mut history, &jobs, &jobids);
+        assert_eq!(acknowledged, 2);
+        assert_eq!(unmatched, ["123"]);
+
+        let unseen: Vec<_> = jobs
+            .iter()
+            .filter(|job| job.is_displayable() && !history.is_acknowledged(job))
+            .map(Job::jobid_display)
+            .collect();
+        assert_eq!(unseen, ["56938942", "56938943"]);
+
+        // acknowledging everything leaves nothing unseen, pending jobs are never acknowledged
+        let (acknowledged, _) = acknowledge_jobs(&mut history, &jobs, &[]);
+        assert_eq!(acknowledged, 4);
+        assert!(
+            jobs.iter()
+                .filter(|job| job.is_displayable())
+                .all(|job| history.is_acknowledged(job))
+        );
+    }
 }
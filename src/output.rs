@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+
+use crate::columns::Column;
+use crate::efficiency::Efficiency;
+use crate::exit_code::explain;
+use crate::{INPUT_DATE_FORMAT, Job};
+
+/// Bumped whenever a field of [`JobRecord`] is renamed, removed or changes type
+const SCHEMA_VERSION: u32 = 1;
+
+/// How the list of jobs is written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Coloured table for humans
+    #[default]
+    Table,
+    /// A single JSON document with all jobs
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// Comma-separated values with a header row
+    Csv,
+    /// Tab-separated values with a header row
+    Tsv,
+}
+
+/// Machine-readable representation of a [`Job`]
+#[derive(Debug, Serialize)]
+struct JobRecord<'a> {
+    schema_version: u32,
+    jobid: String,
+    jobid_base: usize,
+    array_index: Option<usize>,
+    jobname: &'a str,
+    alloccpus: usize,
+    elapsed_seconds: Option<u64>,
+    start: Option<String>,
+    end: Option<String>,
+    state: String,
+    total_cpu_seconds: Option<u64>,
+    max_rss_bytes: Option<u64>,
+    req_mem_bytes: Option<u64>,
+    time_limit_seconds: Option<u64>,
+    /// TotalCPU over Elapsed × AllocCPUS
+    cpu_efficiency: Option<f64>,
+    /// MaxRSS over ReqMem
+    memory_efficiency: Option<f64>,
+    /// Elapsed over Timelimit
+    time_utilization: Option<f64>,
+    /// `<status>:<signal>` as reported by sacct
+    exit_code: Option<String>,
+    derived_exit_code: Option<String>,
+    /// Why the job ended the way it did, if it didn't succeed
+    explanation: Option<String>,
+    /// Additional sacct fields requested with `--columns`, as reported by sacct
+    extra: &'a BTreeMap<String, String>,
+}
+
+/// Top-level document written by `--output json`
+#[derive(Debug, Serialize)]
+struct JobsDocument<'a> {
+    schema_version: u32,
+    jobs: Vec<JobRecord<'a>>,
+}
+
+fn iso_timestamp(date: Option<NaiveDateTime>) -> Option<String> {
+    date.map(|d| d.format(INPUT_DATE_FORMAT).to_string())
+}
+
+impl<'a> JobRecord<'a> {
+    fn new(job: &'a Job) -> Self {
+        let efficiency = Efficiency::of(job);
+        JobRecord {
+            schema_version: SCHEMA_VERSION,
+            jobid: job.jobid_display(),
+            jobid_base: job.jobid_base,
+            array_index: job.array_index,
+            jobname: &job.jobname,
+            alloccpus: job.alloccpus,
+            elapsed_seconds: job.elapsed_seconds(),
+            start: iso_timestamp(job.start),
+            end: iso_timestamp(job.end),
+            state: job.state.to_string(),
+            total_cpu_seconds: job.resources.total_cpu,
+            max_rss_bytes: job.resources.max_rss,
+            req_mem_bytes: job.resources.req_mem,
+            time_limit_seconds: job.resources.time_limit,
+            cpu_efficiency: efficiency.cpu,
+            memory_efficiency: efficiency.memory,
+            time_utilization: efficiency.time,
+            exit_code: job.exit_codes.exit_code.map(|code| code.to_string()),
+            derived_exit_code: job
+                .exit_codes
+                .derived_exit_code
+                .map(|code| code.to_string()),
+            explanation: explain(&job.state, &job.exit_codes),
+            extra: &job.extra,
+        }
+    }
+}
+
+/// Serializes `jobs` as one pretty-printed JSON document
+pub(crate) fn render_json<'a>(jobs: impl IntoIterator<Item = &'a Job>) -> Result<String> {
+    let document = JobsDocument {
+        schema_version: SCHEMA_VERSION,
+        jobs: jobs.into_iter().map(JobRecord::new).collect(),
+    };
+    serde_json::to_string_pretty(&document).context("unable to serialize jobs to JSON")
+}
+
+/// `jobs` as a JSON array of the same objects `--output json` writes, for embedding in other
+/// documents
+pub(crate) fn job_records<'a>(
+    jobs: impl IntoIterator<Item = &'a Job>,
+) -> Result<serde_json::Value> {
+    let records: Vec<_> = jobs.into_iter().map(JobRecord::new).collect();
+    serde_json::to_value(records).context("unable to serialize jobs to JSON")
+}
+
+/// Serializes `jobs` as newline-delimited JSON, one job per line
+pub(crate) fn render_ndjson<'a>(jobs: impl IntoIterator<Item = &'a Job>) -> Result<Vec<String>> {
+    jobs.into_iter()
+        .map(|job| {
+            serde_json::to_string(&JobRecord::new(job)).context("unable to serialize job to JSON")
+        })
+        .collect()
+}
+
+/// Quotes `value` if it contains the delimiter, a quote or a line break, doubling embedded quotes
+fn quote_field(value: &str, delimiter: char) -> String {
+    if value.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Renders a header row of the `columns`' sacct field names followed by one row per job,
+/// separated by `delimiter`
+pub(crate) fn render_delimited<'a>(
+    jobs: impl IntoIterator<Item = &'a Job>,
+    columns: &[Column],
+    delimiter: char,
+) -> Vec<String> {
+    let join = |values: Vec<String>| {
+        values
+            .iter()
+            .map(|value| quote_field(value, delimiter))
+            .collect::<Vec<_>>()
+            .join(&delimiter.to_string())
+    };
+
+    let header = join(columns.iter().map(|column| column.name.clone()).collect());
+    let rows = jobs.into_iter().map(|job| {
+        join(
+            columns
+                .iter()
+                .map(|column| column.plain_value(job))
+                .collect(),
+        )
+    });
+    std::iter::once(header).chain(rows).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::efficiency::Resources;
+    use crate::exit_code::{ExitCode, ExitCodes};
+    use crate::job_state::JobState;
+    use serde_json::{Value, json};
+
+    fn job() -> Job {
+        Job {
+            jobid_base: 56938944,
+            array_index: Some(3),
+            jobname: "relax \"slab\"".to_string(),
+            alloccpus: 2,
+            elapsed: "1-02:03:04".to_string(),
+            start: Some(
+                NaiveDateTime::parse_from_str("2023-04-22T16:15:05", INPUT_DATE_FORMAT).unwrap(),
+            ),
+            end: None,
+            state: JobState::Failed,
+            resources: Resources {
+                total_cpu: Some(93784),
+                max_rss: Some(1024),
+                req_mem: None,
+                time_limit: Some(2 * 93784),
+            },
+            exit_codes: ExitCodes {
+                exit_code: ExitCode::parse("0:9"),
+                derived_exit_code: ExitCode::parse("0:0"),
+            },
+            extra: BTreeMap::from([("partition".to_string(), "gpu".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    fn columns(names: &[&str]) -> Vec<Column> {
+        names.iter().map(|name| Column::new(name)).collect()
+    }
+
+    #[test]
+    fn delimited_header_follows_columns() {
+        let rows = render_delimited([&job()], &columns(&["JobID", "Partition", "Comment"]), ',');
+        assert_eq!(rows, ["jobid,partition,comment", "56938944_3,gpu,"]);
+    }
+
+    #[test]
+    fn json_document_has_typed_fields() {
+        let job = job();
+        let value: Value = serde_json::from_str(&render_json([&job]).unwrap()).unwrap();
+        assert_eq!(value["schema_version"], json!(SCHEMA_VERSION));
+        assert_eq!(
+            value["jobs"][0],
+            json!({
+                "schema_version": SCHEMA_VERSION,
+                "jobid": "56938944_3",
+                "jobid_base": 56938944,
+                "array_index": 3,
+                "jobname": "relax \"slab\"",
+                "alloccpus": 2,
+                "elapsed_seconds": 93784,
+                "start": "2023-04-22T16:15:05",
+                "end": null,
+                "state": "FAILED",
+                "total_cpu_seconds": 93784,
+                "max_rss_bytes": 1024,
+                "req_mem_bytes": null,
+                "time_limit_seconds": 187568,
+                "cpu_efficiency": 0.5,
+                "memory_efficiency": null,
+                "time_utilization": 0.5,
+                "exit_code": "0:9",
+                "derived_exit_code": "0:0",
+                "explanation": "killed (SIGKILL), likely out of memory or over a resource limit",
+                "extra": {"partition": "gpu"},
+            })
+        );
+    }
+
+    #[test]
+    fn ndjson_is_one_object_per_line() {
+        let jobs = [job(), job()];
+        let lines = render_ndjson(&jobs).unwrap();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            assert!(!line.contains('\n'));
+            let value: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(value["jobid"], json!("56938944_3"));
+        }
+    }
+
+    #[test]
+    fn csv_quotes_fields_containing_the_delimiter() {
+        let mut job = job();
+        job.jobname = "relax, \"slab\"".to_string();
+        let rows = render_delimited([&job], &columns(&["jobid", "jobname", "end"]), ',');
+        assert_eq!(
+            rows,
+            ["jobid,jobname,end", "56938944_3,\"relax, \"\"slab\"\"\","]
+        );
+    }
+
+    #[test]
+    fn tsv_only_quotes_when_needed() {
+        let mut job = job();
+        job.jobname = "a, b".to_string();
+        let rows = render_delimited([&job], &columns(&["jobname", "start"]), '\t');
+        assert_eq!(rows[1], "a, b\t2023-04-22T16:15:05");
+
+        job.jobname = "a\tb".to_string();
+        let rows = render_delimited([&job], &columns(&["jobname"]), '\t');
+        assert_eq!(rows[1], "\"a\tb\"");
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Context, Result, bail};
+use std::fmt;
+
+/// A set of array indices, kept as sorted, disjoint `(first, last)` ranges rather than one by
+/// one, as a pending array job may have millions of tasks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Ranges(Vec<(usize, usize)>);
+
+impl Ranges {
+    /// How many indices there are
+    pub(crate) fn count(&self) -> usize {
+        self.0.iter().map(|&(first, last)| last - first + 1).sum()
+    }
+
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        let after = self.0.partition_point(|&(first, _)| first <= index);
+        after > 0 && index <= self.0[after - 1].1
+    }
+
+    /// Every index, in order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().flat_map(|&(first, last)| first..=last)
+    }
+
+    /// Adds every index of `other`
+    pub(crate) fn extend(&mut self, other: &Ranges) {
+        self.0.extend(&other.0);
+        self.normalize();
+    }
+
+    /// Sorts the ranges and merges those that overlap or touch
+    fn normalize(&mut self) {
+        self.0.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(self.0.len());
+        for &(first, last) in &self.0 {
+            match merged
+                .last_mut()
+                .filter(|(_, end)| first <= end.saturating_add(1))
+            {
+                Some((_, end)) => *end = last.max(*end),
+                None => merged.push((first, last)),
+            }
+        }
+        self.0 = merged;
+    }
+}
+
+impl FromIterator<usize> for Ranges {
+    fn from_iter<I: IntoIterator<Item = usize>>(indices: I) -> Self {
+        let mut ranges = Ranges(indices.into_iter().map(|index| (index, index)).collect());
+        ranges.normalize();
+        ranges
+    }
+}
+
+/// SLURM's compressed range syntax, e.g. `3,17-19,200`
+impl fmt::Display for Ranges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, &(first, last)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            if first == last {
+                write!(f, "{first}")?;
+            } else {
+                write!(f, "{first}-{last}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a SLURM array index list such as `3`, `1-10`, `3,17-19,200` or `0-15:4`, optionally in
+/// brackets and with a `%` throttle (`[1-1000%50]`)
+pub(crate) fn parse_ranges(value: &str) -> Result<Ranges> {
+    let list = value.trim();
+    let list = list
+        .strip_prefix('[')
+        .and_then(|list| list.strip_suffix(']'))
+        .unwrap_or(list);
+    // the throttle limits how many tasks run at once, it doesn't change which ones exist
+    let list = list.split_once('%').map_or(list, |(list, _)| list);
+    if list.is_empty() {
+        bail!("empty index list {value:?}");
+    }
+
+    let mut ranges = Vec::new();
+    for part in list.split(',') {
+        let (range, step) = match part.split_once(':') {
+            Some((range, step)) => (range, parse_index(step)?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("step of {part:?} must be at least 1");
+        }
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (parse_index(first)?, parse_index(last)?),
+            None => (parse_index(range)?, parse_index(range)?),
+        };
+        if first > last {
+            bail!("range {part:?} ends before it starts");
+        }
+        // sacct never lists steps, only people do, so the indices of a stepped range are few
+        if step == 1 {
+            ranges.push((first, last));
+        } else {
+            ranges.extend((first..=last).step_by(step).map(|index| (index, index)));
+        }
+    }
+    let mut ranges = Ranges(ranges);
+    ranges.normalize();
+    Ok(ranges)
+}
+
+/// Formats `indices` in SLURM's compressed range syntax, e.g. `3,17-19,200`. Inverse of
+/// [`parse_ranges`] without steps.
+pub(crate) fn format_ranges(indices: &[usize]) -> String {
+    indices.iter().copied().collect::<Ranges>().to_string()
+}
+
+fn parse_index(value: &str) -> Result<usize> {
+    value
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid array index {value:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! parse_ranges_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (input, expected): (&str, Option<Vec<usize>>) = $value;
+                let indices = parse_ranges(input).ok().map(|ranges| ranges.iter().collect());
+                assert_eq!(expected, indices);
+            }
+    )*
+        }
+    }
+    parse_ranges_tests! {
+        parse_ranges0: ("3", Some(vec![3])),
+        parse_ranges1: ("1-5", Some(vec![1, 2, 3, 4, 5])),
+        parse_ranges2: ("3,17-19,200", Some(vec![3, 17, 18, 19, 200])),
+        parse_ranges3: ("[1-3]", Some(vec![1, 2, 3])),
+        parse_ranges4: ("[1-4%2]", Some(vec![1, 2, 3, 4])),
+        parse_ranges5: ("0-15:5", Some(vec![0, 5, 10, 15])),
+        parse_ranges6: ("5,1-3,2", Some(vec![1, 2, 3, 5])),
+        parse_ranges7: ("", None),
+        parse_ranges8: ("5-1", None),
+        parse_ranges9: ("1-x", None),
+        parse_ranges10: ("1-4:0", None),
+    }
+
+    #[test]
+    fn large_arrays_are_not_expanded() {
+        let ranges = parse_ranges("[0-4000000%10]").unwrap();
+        assert_eq!(ranges.0, [(0, 4_000_000)]);
+        assert_eq!(ranges.count(), 4_000_001);
+        assert!(ranges.contains(4_000_000));
+        assert!(!ranges.contains(4_000_001));
+
+        let mut ranges = parse_ranges("1-3,7").unwrap();
+        ranges.extend(&parse_ranges("4-5,10").unwrap());
+        assert_eq!(ranges.to_string(), "1-5,7,10");
+        assert!(!ranges.contains(0) && !ranges.contains(6) && ranges.contains(10));
+    }
+
+    macro_rules! format_ranges_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (input, expected): (&[usize], &str) = $value;
+                assert_eq!(expected, format_ranges(input));
+            }
+    )*
+        }
+    }
+    format_ranges_tests! {
+        format_ranges0: (&[], ""),
+        format_ranges1: (&[7], "7"),
+        format_ranges2: (&[3, 17, 18, 19, 200], "3,17-19,200"),
+        format_ranges3: (&[19, 3, 18, 17, 3], "3,17-19"),
+        format_ranges4: (&[1, 2, 4, 5], "1-2,4-5"),
+    }
+}
@@ -0,0 +1,820 @@
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::NaiveDateTime;
+use colored::Colorize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::backend::Backend;
+use crate::job_state::Category;
+use crate::ranges::format_ranges;
+use crate::state::StateDir;
+use crate::{
+    FORMAT_CMD, Job, ParsedJobId, SACCT_DELIMITER, check_job, collect_jobs, matches_jobid,
+    open_history, parse_jobs,
+};
+
+/// sacct fields telling where and how a job was submitted. SubmitLine comes last as it's the
+/// only one that may contain the delimiter.
+const SUBMIT_FIELDS: [&str; 2] = ["workdir", "submitline"];
+
+/// Short sbatch options that don't take a value
+const SHORT_FLAGS: &str = "hHIkOQsvVW";
+/// Long sbatch options that don't take a value, or only one attached with `=`
+const LONG_FLAGS: [&str; 22] = [
+    "contiguous",
+    "exclusive",
+    "get-user-env",
+    "help",
+    "hold",
+    "ignore-pbs",
+    "immediate",
+    "kill-on-invalid-dep",
+    "no-kill",
+    "no-requeue",
+    "overcommit",
+    "oversubscribe",
+    "parsable",
+    "quiet",
+    "reboot",
+    "requeue",
+    "spread-job",
+    "test-only",
+    "usage",
+    "use-min-nodes",
+    "verbose",
+    "wait",
+];
+/// Long names of the short sbatch options that overrides may replace
+const SHORT_ALIASES: [(char, &str); 4] = [
+    ('a', "array"),
+    ('p', "partition"),
+    ('t', "time"),
+    ('J', "job-name"),
+];
+
+/// Marks the end of a batch script passed in a here-document by `--dry-run` output
+const HEREDOC_DELIMITER: &str = "JOBS_DONE_SCRIPT";
+
+/// Splits a command line into words like a POSIX shell would, honouring quotes and backslashes
+fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated ' in {line:?}"),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => bail!("unterminated \" in {line:?}"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated \" in {line:?}"),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Quotes `word` for a POSIX shell, unless it's safe as it is
+fn shell_quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// The long name of the sbatch option `arg`, e.g. `time` for `--time=10`, `-t` and `-t10`
+fn option_name(arg: &str) -> Option<String> {
+    if let Some(long) = arg.strip_prefix("--") {
+        let name = long.split_once('=').map_or(long, |(name, _)| name);
+        return Some(name.to_string());
+    }
+    let short = arg.strip_prefix('-')?.chars().next()?;
+    Some(
+        match SHORT_ALIASES.iter().find(|(alias, _)| *alias == short) {
+            Some((_, name)) => name.to_string(),
+            None => short.to_string(),
+        },
+    )
+}
+
+/// The value of the sbatch option `option`, e.g. `1-10` for `--array=1-10`, `-a 1-10` or
+/// `-a1-10`
+fn option_value(option: &[String]) -> Option<&str> {
+    if let Some(value) = option.get(1) {
+        return Some(value);
+    }
+    match option[0].strip_prefix("--") {
+        Some(long) => long.split_once('=').map(|(_, value)| value),
+        None => option[0].get(2..).filter(|value| !value.is_empty()),
+    }
+}
+
+/// The `%N` throttle of the `--array` spec a job was submitted with, from its submit line or
+/// else the `#SBATCH` directives of its script
+fn array_throttle(submit_line: &SubmitLine, script: Option<&str>) -> Option<String> {
+    let directives = script
+        .into_iter()
+        .flat_map(str::lines)
+        .filter_map(|line| line.trim().strip_prefix("#SBATCH"))
+        .filter_map(|line| SubmitLine::parse(line).ok())
+        .flat_map(|line| line.options);
+    submit_line
+        .options
+        .iter()
+        .cloned()
+        .chain(directives)
+        .filter(|option| option_name(&option[0]).as_deref() == Some("array"))
+        .find_map(|option| {
+            let (_, throttle) = option_value(&option)?.split_once('%')?;
+            Some(throttle.to_string())
+        })
+}
+
+/// A parsed `sbatch` command line
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SubmitLine {
+    /// Every option with its value, if the value is a separate word
+    options: Vec<Vec<String>>,
+    /// The script and its arguments; empty if the script was read from stdin
+    script: Vec<String>,
+}
+
+impl SubmitLine {
+    fn parse(line: &str) -> Result<Self> {
+        let words = split_words(line)?;
+        let mut words = words.into_iter().peekable();
+        // sbatch itself, possibly with its path
+        if words.peek().is_some_and(|word| word.ends_with("sbatch")) {
+            words.next();
+        }
+
+        let mut submit_line = SubmitLine::default();
+        while let Some(word) = words.next() {
+            if word == "--" || !word.starts_with('-') || word == "-" {
+                if word != "--" {
+                    submit_line.script.push(word);
+                }
+                submit_line.script.extend(words);
+                break;
+            }
+            let takes_value = match word.strip_prefix("--") {
+                Some(long) => !long.contains('=') && !LONG_FLAGS.contains(&long),
+                // `-t10` has its value attached
+                None => word.len() == 2 && !SHORT_FLAGS.contains(&word[1..]),
+            };
+            let mut option = vec![word];
+            if takes_value {
+                option.extend(words.next());
+            }
+            submit_line.options.push(option);
+        }
+        Ok(submit_line)
+    }
+}
+
+/// An option replacing the one of the same name the job was submitted with, e.g.
+/// `("time", "02:00:00")`
+pub(crate) type Override = (&'static str, String);
+
+/// The overrides for the `--time` and `--mem` given on the command line
+pub(crate) fn overrides(time: Option<&str>, mem: Option<&str>) -> Vec<Override> {
+    [("time", time), ("mem", mem)]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?.to_string())))
+        .collect()
+}
+
+/// How to submit a job again
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Submission {
+    /// The job this repeats
+    pub(crate) jobid: String,
+    workdir: PathBuf,
+    args: Vec<String>,
+    /// The recovered batch script, passed on stdin. `None` when `args` name the script file.
+    script: Option<String>,
+}
+
+impl Submission {
+    /// Recovers how `job` was submitted and applies `overrides` to it. An array task is
+    /// submitted again on its own, as a single-task array.
+    pub(crate) fn plan(backend: &dyn Backend, job: &Job, overrides: &[Override]) -> Result<Self> {
+        let jobid = job.jobid_display();
+        let details = backend.job_details(&jobid, &SUBMIT_FIELDS)?;
+        let line = details.lines().next().unwrap_or_default();
+        let (workdir, submit_line) = line.split_once(SACCT_DELIMITER).unwrap_or((line, ""));
+        if workdir.is_empty() {
+            bail!("couldn't find the working directory of job {jobid}");
+        }
+        let submit_line = SubmitLine::parse(submit_line)
+            .with_context(|| format!("unable to parse the submit line of job {jobid}"))?;
+
+        let mut overrides = overrides.to_vec();
+        let overrides_array = overrides.iter().any(|(name, _)| *name == "array");
+        if let Some(index) = job.array_index.filter(|_| !overrides_array) {
+            overrides.push(("array", index.to_string()));
+        }
+        let script = backend.batch_script(&jobid)?;
+        Self::from_parts(
+            jobid,
+            PathBuf::from(workdir),
+            submit_line,
+            script,
+            &overrides,
+        )
+    }
+
+    fn from_parts(
+        jobid: String,
+        workdir: PathBuf,
+        submit_line: SubmitLine,
+        script: Option<String>,
+        overrides: &[Override],
+    ) -> Result<Self> {
+        // an array spec replacing the original keeps its throttle, unless it's a single task
+        let throttle = array_throttle(&submit_line, script.as_deref());
+        let overrides: Vec<_> = overrides
+            .iter()
+            .map(|(name, value)| match &throttle {
+                Some(throttle)
+                    if *name == "array"
+                        && !value.contains('%')
+                        && value.parse::<usize>().is_err() =>
+                {
+                    (*name, format!("{value}%{throttle}"))
+                }
+                _ => (*name, value.clone()),
+            })
+            .collect();
+        let mut args: Vec<_> = submit_line
+            .options
+            .into_iter()
+            .filter(|option| {
+                let name = option_name(&option[0]);
+                !overrides
+                    .iter()
+                    .any(|(overridden, _)| name.as_deref() == Some(*overridden))
+            })
+            .flatten()
+            .collect();
+        args.extend(
+            overrides
+                .iter()
+                .map(|(name, value)| format!("--{name}={value}")),
+        );
+
+        // the recovered script is what actually ran, but a file is the only way to pass arguments
+        let script = match (script, submit_line.script.len()) {
+            (Some(script), 0 | 1) => Some(script),
+            (_, 1..) => {
+                args.extend(submit_line.script);
+                None
+            }
+            (None, 0) => {
+                return Err(anyhow!(
+                    "unable to recover the batch script of job {jobid}: SLURM no longer has it"
+                ));
+            }
+        };
+        Ok(Submission {
+            jobid,
+            workdir,
+            args,
+            script,
+        })
+    }
+
+    /// A shell command that submits the job again, with the script in a here-document
+    pub(crate) fn command_line(&self) -> String {
+        let mut command = format!(
+            "cd {} && sbatch",
+            shell_quote(&self.workdir.to_string_lossy())
+        );
+        for arg in &self.args {
+            command.push(' ');
+            command.push_str(&shell_quote(arg));
+        }
+        if let Some(script) = &self.script {
+            command.push_str(&format!(" <<'{HEREDOC_DELIMITER}'\n{script}"));
+            if !script.ends_with('\n') {
+                command.push('\n');
+            }
+            command.push_str(HEREDOC_DELIMITER);
+        }
+        command
+    }
+
+    /// Runs `sbatch` in the job's working directory. Returns what sbatch printed, e.g.
+    /// `Submitted batch job 123`.
+    pub(crate) fn submit(&self) -> Result<String> {
+        let mut child = Command::new("sbatch")
+            .args(&self.args)
+            .current_dir(&self.workdir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to execute sbatch in {}", self.workdir.display()))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        if let Some(script) = &self.script {
+            stdin
+                .write_all(script.as_bytes())
+                .context("unable to pass the batch script to sbatch")?;
+        }
+        drop(stdin);
+
+        let output = child
+            .wait_with_output()
+            .context("failed to execute sbatch")?;
+        if !output.status.success() {
+            bail!(
+                "sbatch failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+/// The jobs to resubmit: those `jobids` refer to, unless they were cancelled, or if none are
+/// given every job that finished since `since` and failed. Only jobs in `states` are kept
+/// instead, if any are given.
+pub(crate) fn select_jobs(
+    backend: &dyn Backend,
+    jobids: &[String],
+    since: Option<NaiveDateTime>,
+    user: &str,
+    states: &[String],
+    fields: &[&str],
+) -> Result<Vec<Job>> {
+    let mut jobs = Vec::new();
+    if jobids.is_empty() {
+        let since = since.ok_or_else(|| anyhow!("give job IDs or --since"))?;
+        jobs = collect_jobs(backend, since, user, &[], fields)?;
+    }
+    for jobid in jobids {
+        let output = backend.job_details(jobid, fields)?;
+        let found: Vec<_> = parse_jobs(&output, fields)?
+            .into_iter()
+            .filter(|job| matches_jobid(job, jobid))
+            .collect();
+        if found.is_empty() {
+            bail!("no job {jobid} found");
+        }
+        jobs.extend(found);
+    }
+
+    jobs.retain(|job| {
+        job.is_finished()
+            && if states.is_empty() {
+                match job.state.category() {
+                    Category::Failure => true,
+                    Category::Success => !jobids.is_empty(),
+                    // cancelled on purpose, unless asked for by state
+                    Category::Cancelled | Category::InProgress => false,
+                }
+            } else {
+                states.iter().any(|state| job.state.matches(state))
+            }
+    });
+    Ok(jobs)
+}
+
+/// The job ID in what sbatch printed, `Submitted batch job 123` or `123;cluster` with
+/// `--parsable`
+fn submitted_jobid(output: &str) -> Option<&str> {
+    let last = output.split_whitespace().last()?;
+    let jobid = last.split(';').next()?;
+    jobid.chars().all(|c| c.is_ascii_digit()).then_some(jobid)
+}
+
+/// Records in the history that `jobs` were resubmitted as `new_jobid`. Array tasks keep their
+/// index in the new array.
+fn record(state_dir: &StateDir, jobs: &[&Job], new_jobid: &str) -> Result<()> {
+    let _lock = state_dir.lock()?;
+    let mut history = open_history(&state_dir.history_file(), &state_dir.legacy_log_file())?;
+    for job in jobs {
+        let resubmitted_as = match job.array_index {
+            Some(index) => format!("{new_jobid}_{index}"),
+            None => new_jobid.to_string(),
+        };
+        history.record_resubmission(job, &resubmitted_as);
+    }
+    history.save()
+}
+
+/// Runs every submission, or with `dry_run` only prints how it would be done. Each comes with
+/// the jobs it repeats, which are recorded as resubmitted.
+fn submit_all(
+    state_dir: &StateDir,
+    submissions: Vec<(Submission, Vec<&Job>)>,
+    dry_run: bool,
+) -> Result<()> {
+    for (submission, jobs) in submissions {
+        if dry_run {
+            println!(
+                "# resubmit {}\n{}",
+                submission.jobid,
+                submission.command_line()
+            );
+            continue;
+        }
+        let submitted = submission
+            .submit()
+            .with_context(|| format!("unable to resubmit job {}", submission.jobid))?;
+        println!("{}: {submitted}", submission.jobid);
+        match submitted_jobid(&submitted) {
+            Some(new_jobid) => record(state_dir, &jobs, new_jobid)?,
+            None => eprintln!(
+                "{} unable to tell the new job ID from {submitted:?}, not recording the resubmission",
+                "warning:".yellow().bold()
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Drops the jobs that were resubmitted before from `jobs`, telling which
+pub(crate) fn skip_resubmitted(state_dir: &StateDir, jobs: &mut Vec<Job>) -> Result<()> {
+    let history = open_history(&state_dir.history_file(), &state_dir.legacy_log_file())?;
+    jobs.retain(|job| match history.resubmitted_as(job) {
+        Some(resubmitted_as) => {
+            eprintln!(
+                "Skipping {}, already resubmitted as {resubmitted_as}",
+                job.jobid_display()
+            );
+            false
+        }
+        None => true,
+    });
+    Ok(())
+}
+
+/// Submits every job in `jobs` again, or with `dry_run` only prints how it would be done
+pub(crate) fn run(
+    backend: &dyn Backend,
+    state_dir: &StateDir,
+    jobs: &[Job],
+    overrides: &[Override],
+    dry_run: bool,
+) -> Result<()> {
+    if jobs.is_empty() {
+        bail!("no finished jobs to resubmit");
+    }
+    let submissions = jobs
+        .iter()
+        .map(|job| Ok((Submission::plan(backend, job, overrides)?, vec![job])))
+        .collect::<Result<Vec<_>>>()?;
+    submit_all(state_dir, submissions, dry_run)
+}
+
+/// A single submission repeating all `tasks` of one array job, with their indices as the
+/// `--array` spec
+fn plan_array(backend: &dyn Backend, tasks: &[&Job], overrides: &[Override]) -> Result<Submission> {
+    let first = tasks
+        .first()
+        .ok_or_else(|| anyhow!("no array tasks to resubmit"))?;
+    let indices: Vec<_> = tasks.iter().filter_map(|task| task.array_index).collect();
+    let array = format_ranges(&indices);
+    let mut overrides: Vec<_> = overrides
+        .iter()
+        .filter(|(name, _)| *name != "array")
+        .cloned()
+        .collect();
+    overrides.push(("array", array.clone()));
+
+    let mut submission = Submission::plan(backend, first, &overrides)?;
+    submission.jobid = format!("{}_[{array}]", first.jobid_base);
+    Ok(submission)
+}
+
+/// Submits the tasks of array job `base` that failed (or are in one of `states`)
+/// again, as one new array job. Tasks that were resubmitted before are left out.
+pub(crate) fn run_array(
+    backend: &dyn Backend,
+    state_dir: &StateDir,
+    base: &str,
+    states: &[String],
+    overrides: &[Override],
+    dry_run: bool,
+) -> Result<()> {
+    if !matches!(check_job(base), ParsedJobId::Singular(_)) {
+        bail!("expected the ID of an array job, e.g. 123, got {base:?}");
+    }
+    let mut tasks = select_jobs(backend, &[base.to_string()], None, "", states, &FORMAT_CMD)?;
+    if states.is_empty() {
+        tasks.retain(|task| task.state.category() == Category::Failure);
+    }
+    if tasks.iter().any(|task| task.array_index.is_none()) {
+        bail!("job {base} is not an array job, use resubmit instead");
+    }
+    skip_resubmitted(state_dir, &mut tasks)?;
+    if tasks.is_empty() {
+        bail!("no finished tasks of array job {base} to resubmit");
+    }
+
+    let tasks: Vec<_> = tasks.iter().collect();
+    let submission = plan_array(backend, &tasks, overrides)?;
+    eprintln!("Resubmitting {} task(s) of array job {base}", tasks.len());
+    submit_all(state_dir, vec![(submission, tasks)], dry_run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Replay, replay_fixtures};
+    use crate::job_state::JobState;
+    use std::fs;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn words_are_split_like_a_shell() {
+        assert_eq!(
+            split_words(r#"sbatch -J "relax slab" --comment='a "b"' c\ d"#).unwrap(),
+            ["sbatch", "-J", "relax slab", "--comment=a \"b\"", "c d"]
+        );
+        assert!(split_words("sbatch -J 'open").is_err());
+        assert_eq!(shell_quote("--time=1:00"), "--time=1:00");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn submit_lines_are_split_into_options_and_script() {
+        let line = SubmitLine::parse("/usr/bin/sbatch -p normal -t10 --hold --mem 8G run.sh -v 3")
+            .unwrap();
+        assert_eq!(
+            line.options,
+            [
+                strings(&["-p", "normal"]),
+                strings(&["-t10"]),
+                strings(&["--hold"]),
+                strings(&["--mem", "8G"]),
+            ]
+        );
+        assert_eq!(line.script, ["run.sh", "-v", "3"]);
+        assert_eq!(SubmitLine::parse("").unwrap(), SubmitLine::default());
+    }
+
+    #[test]
+    fn overrides_replace_options() {
+        let line = SubmitLine::parse("sbatch -t 10 -p normal --mem=1G run.sh").unwrap();
+        let overrides = [("time", "02:00:00".to_string()), ("mem", "4G".to_string())];
+        let submission =
+            Submission::from_parts("1".to_string(), "/w".into(), line, None, &overrides).unwrap();
+        assert_eq!(
+            submission.args,
+            ["-p", "normal", "--time=02:00:00", "--mem=4G", "run.sh"]
+        );
+        assert_eq!(
+            submission.command_line(),
+            "cd /w && sbatch -p normal --time=02:00:00 --mem=4G run.sh"
+        );
+    }
+
+    #[test]
+    fn recovered_scripts_are_passed_on_stdin() {
+        let jobs = select_jobs(
+            &replay_fixtures(),
+            &strings(&["56938943"]),
+            None,
+            "me",
+            &[],
+            &FORMAT_CMD,
+        )
+        .unwrap();
+        let submission = Submission::plan(&replay_fixtures(), &jobs[0], &[]).unwrap();
+        assert_eq!(submission.args, ["-p", "normal", "--mem", "8G"]);
+        let command = submission.command_line();
+        assert!(command.starts_with(
+            "cd /home/me/slab && sbatch -p normal --mem 8G <<'JOBS_DONE_SCRIPT'\n#!/bin/bash\n"
+        ));
+        assert!(command.ends_with("srun vasp_std\nJOBS_DONE_SCRIPT"));
+    }
+
+    #[test]
+    fn array_tasks_are_resubmitted_alone() {
+        let jobs = select_jobs(
+            &replay_fixtures(),
+            &strings(&["56938944"]),
+            None,
+            "me",
+            &[],
+            &FORMAT_CMD,
+        )
+        .unwrap();
+        assert_eq!(jobs.len(), 2);
+        let submission = Submission::plan(&replay_fixtures(), &jobs[1], &[]).unwrap();
+        assert_eq!(submission.args, ["--array=2"]);
+    }
+
+    #[test]
+    fn failed_array_tasks_are_resubmitted_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let replay_dir = dir.path().join("replay");
+        fs::create_dir(&replay_dir).unwrap();
+        let mut sacct = "JobID|JobName|AllocCPUS|Elapsed|State|WorkDir|SubmitLine\n".to_string();
+        for index in 1..=20 {
+            let state = match index {
+                3 | 17 | 18 => "FAILED",
+                19 => "TIMEOUT",
+                _ => "COMPLETED",
+            };
+            sacct.push_str(&format!(
+                "77_{index}|run|1|00:01:00|{state}|/w|sbatch -a 1-20 -t 10 run.sh\n"
+            ));
+        }
+        fs::write(replay_dir.join("jobs.sacct"), sacct).unwrap();
+        let backend = Replay::new(&replay_dir);
+
+        let jobs = select_jobs(&backend, &strings(&["77"]), None, "me", &[], &FORMAT_CMD).unwrap();
+        let failed: Vec<_> = jobs
+            .iter()
+            .filter(|job| job.state != JobState::Completed)
+            .collect();
+        let submission = plan_array(&backend, &failed, &[("time", "20".to_string())]).unwrap();
+        assert_eq!(submission.jobid, "77_[3,17-19]");
+        assert_eq!(
+            submission.command_line(),
+            "cd /w && sbatch --time=20 --array=3,17-19 run.sh"
+        );
+
+        let state_dir = StateDir::resolve(Some(&dir.path().join("state")), "me").unwrap();
+        run_array(&backend, &state_dir, "77", &[], &[], true).unwrap();
+        record(&state_dir, &failed[..3], "99").unwrap();
+        let history =
+            open_history(&state_dir.history_file(), &state_dir.legacy_log_file()).unwrap();
+        assert_eq!(history.resubmitted_as(failed[1]), Some("99_17"));
+        // only task 19 is left to retry
+        let mut remaining =
+            select_jobs(&backend, &strings(&["77"]), None, "me", &[], &FORMAT_CMD).unwrap();
+        remaining.retain(|job| job.state != JobState::Completed);
+        skip_resubmitted(&state_dir, &mut remaining).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].array_index, Some(19));
+        assert!(run_array(&backend, &state_dir, "77_3", &[], &[], true).is_err());
+    }
+
+    #[test]
+    fn array_throttles_are_kept() {
+        let overrides = [("array", "3,17-19".to_string())];
+        for (line, script) in [
+            ("sbatch -a 1-1000%50 run.sh", None),
+            ("sbatch --array=1-1000%50 run.sh", None),
+            ("sbatch -a1-1000%50 run.sh", None),
+            (
+                "sbatch run.sh",
+                Some("#!/bin/bash\n#SBATCH -t 10\n#SBATCH --array 1-1000%50\nrun\n"),
+            ),
+        ] {
+            let submit_line = SubmitLine::parse(line).unwrap();
+            let script = script.map(str::to_string);
+            let submission = Submission::from_parts(
+                "1".to_string(),
+                "/w".into(),
+                submit_line,
+                script,
+                &overrides,
+            )
+            .unwrap();
+            assert!(
+                submission.args.contains(&"--array=3,17-19%50".to_string()),
+                "{line}"
+            );
+        }
+
+        // a single task, or a spec with a throttle of its own, is left as it is
+        for (value, expected) in [("7", "--array=7"), ("1-5%2", "--array=1-5%2")] {
+            let submit_line = SubmitLine::parse("sbatch -a 1-1000%50 run.sh").unwrap();
+            let overrides = [("array", value.to_string())];
+            let submission =
+                Submission::from_parts("1".to_string(), "/w".into(), submit_line, None, &overrides)
+                    .unwrap();
+            assert_eq!(submission.args, [expected, "run.sh"]);
+        }
+    }
+
+    #[test]
+    fn new_jobids_are_read_from_sbatch_output() {
+        assert_eq!(submitted_jobid("Submitted batch job 123"), Some("123"));
+        assert_eq!(submitted_jobid("123;cluster"), Some("123"));
+        assert_eq!(submitted_jobid("sbatch: error"), None);
+    }
+
+    #[test]
+    fn jobs_without_a_script_cannot_be_resubmitted() {
+        let line = SubmitLine::parse("sbatch --wrap 'sleep 1'").unwrap();
+        assert!(Submission::from_parts("1".to_string(), "/w".into(), line, None, &[]).is_err());
+    }
+
+    #[test]
+    fn selection_by_state_and_time() {
+        let since = chrono::Local::now().naive_local();
+        let select = |states: &[&str]| -> Vec<String> {
+            select_jobs(
+                &replay_fixtures(),
+                &[],
+                Some(since),
+                "me",
+                &strings(states),
+                &FORMAT_CMD,
+            )
+            .unwrap()
+            .iter()
+            .map(Job::jobid_display)
+            .collect()
+        };
+        assert_eq!(select(&[]), ["56938943", "56938944_2"]);
+        assert_eq!(select(&["timeout"]), ["56938944_2"]);
+        // jobs asked for by ID are resubmitted whatever their state
+        let jobs = select_jobs(
+            &replay_fixtures(),
+            &strings(&["56938942"]),
+            None,
+            "me",
+            &[],
+            &FORMAT_CMD,
+        )
+        .unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert!(select_jobs(&replay_fixtures(), &[], None, "me", &[], &FORMAT_CMD).is_err());
+    }
+
+    #[test]
+    fn cancelled_jobs_are_only_resubmitted_when_asked_for() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("jobs.sacct"),
+            "JobID|JobName|AllocCPUS|Elapsed|Start|End|State\n\
+             77_1|a|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|CANCELLED by 1234\n\
+             77_2|a|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|FAILED\n\
+             78|b|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|CANCELLED by 1234\n",
+        )
+        .unwrap();
+        let backend = Replay::new(dir.path());
+        let since = chrono::Local::now().naive_local();
+        let select = |jobids: &[&str], states: &[&str]| -> Vec<String> {
+            select_jobs(
+                &backend,
+                &strings(jobids),
+                Some(since),
+                "me",
+                &strings(states),
+                &FORMAT_CMD,
+            )
+            .unwrap()
+            .iter()
+            .map(Job::jobid_display)
+            .collect()
+        };
+        assert_eq!(select(&[], &[]), ["77_2"]);
+        assert!(select(&["78"], &[]).is_empty());
+        assert_eq!(select(&[], &["CA"]), ["77_1", "78"]);
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{Context, Result, anyhow};
+use fs4::FileExt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Files kept in the state directory
+const HISTORY_FILE: &str = "history.jsonl";
+const LEGACY_LOG_FILE: &str = "log_file";
+const DATE_FILE: &str = "date_file";
+const LOCK_FILE: &str = "lock";
+
+/// Used when the cluster's name can't be determined
+const DEFAULT_CLUSTER: &str = "default";
+
+/// Per-user, per-cluster directory holding the session date and the job history.
+///
+/// Defaults to `$XDG_STATE_HOME/jobs_done/<cluster>/<user>/`, falling back to
+/// `~/.local/state` when `XDG_STATE_HOME` isn't set.
+#[derive(Debug)]
+pub(crate) struct StateDir {
+    dir: PathBuf,
+}
+
+impl StateDir {
+    /// Resolves and creates the state directory for `user`. An `override_dir` is used as is,
+    /// without appending the cluster and user.
+    pub(crate) fn resolve(override_dir: Option<&Path>, user: &str) -> Result<Self> {
+        let dir = match override_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => default_state_dir(
+                std::env::var_os("XDG_STATE_HOME").map(PathBuf::from),
+                std::env::var_os("HOME").map(PathBuf::from),
+                &cluster_name(),
+                user,
+            )
+            .ok_or_else(|| {
+                anyhow!("unable to find a state directory: neither XDG_STATE_HOME nor HOME is set")
+            })?,
+        };
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("unable to create state directory {}", dir.display()))?;
+        Ok(StateDir { dir })
+    }
+
+    pub(crate) fn history_file(&self) -> PathBuf {
+        self.dir.join(HISTORY_FILE)
+    }
+
+    pub(crate) fn legacy_log_file(&self) -> PathBuf {
+        self.dir.join(LEGACY_LOG_FILE)
+    }
+
+    pub(crate) fn date_file(&self) -> PathBuf {
+        self.dir.join(DATE_FILE)
+    }
+
+    /// Blocks until this process holds the exclusive advisory lock on the state directory. Hold
+    /// the returned guard while reading and updating state that other sessions may update too.
+    pub(crate) fn lock(&self) -> Result<StateLock> {
+        let path = self.dir.join(LOCK_FILE);
+        let file = File::create(&path)
+            .with_context(|| format!("unable to open lock file {}", path.display()))?;
+        FileExt::lock(&file).with_context(|| format!("unable to lock {}", path.display()))?;
+        Ok(StateLock { _file: file })
+    }
+
+    /// Copies the state files that older versions kept in `old_dir` (next to the executable)
+    /// into this directory, unless this directory already has state of its own. The originals
+    /// are left alone as the old location may be read-only or shared. Returns whether anything
+    /// was migrated.
+    pub(crate) fn migrate_from(&self, old_dir: &Path) -> Result<bool> {
+        let has_state = [HISTORY_FILE, LEGACY_LOG_FILE, DATE_FILE]
+            .iter()
+            .any(|file| self.dir.join(file).exists());
+        if has_state || old_dir == self.dir {
+            return Ok(false);
+        }
+
+        let mut migrated = false;
+        for file in [HISTORY_FILE, LEGACY_LOG_FILE, DATE_FILE] {
+            let old = old_dir.join(file);
+            if old.is_file() {
+                fs::copy(&old, self.dir.join(file))
+                    .with_context(|| format!("unable to migrate {}", old.display()))?;
+                migrated = true;
+            }
+        }
+        Ok(migrated)
+    }
+}
+
+/// Exclusive lock on a [`StateDir`], released when dropped
+#[derive(Debug)]
+pub(crate) struct StateLock {
+    _file: File,
+}
+
+/// Replaces `path` with `contents` so that readers see either the old or the new file, never a
+/// partially written one. The data goes to a temporary file next to `path` first, which is
+/// then renamed over it.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file path", path.display()))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let write = || -> Result<()> {
+        let mut fd = File::create(&tmp_path)
+            .with_context(|| format!("unable to create {}", tmp_path.display()))?;
+        fd.write_all(contents)
+            .with_context(|| format!("unable to write {}", tmp_path.display()))?;
+        fd.sync_all()
+            .with_context(|| format!("unable to flush {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path).with_context(|| format!("unable to replace {}", path.display()))
+    };
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+fn default_state_dir(
+    xdg_state_home: Option<PathBuf>,
+    home: Option<PathBuf>,
+    cluster: &str,
+    user: &str,
+) -> Option<PathBuf> {
+    // the XDG spec says relative paths are invalid and should be ignored
+    let state_home = xdg_state_home
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home.map(|home| home.join(".local").join("state")))?;
+    Some(
+        state_home
+            .join("jobs_done")
+            .join(sanitize(cluster))
+            .join(sanitize(user)),
+    )
+}
+
+/// Keeps a name from escaping its directory
+fn sanitize(name: &str) -> String {
+    match name.replace(['/', '\0'], "_").as_str() {
+        "" | "." | ".." => "_".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Name of the SLURM cluster this is running on: `SLURM_CLUSTER_NAME` if set (inside jobs),
+/// otherwise the `ClusterName` from `slurm.conf`
+fn cluster_name() -> String {
+    if let Some(name) = std::env::var("SLURM_CLUSTER_NAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+    {
+        return name;
+    }
+
+    let conf = std::env::var_os("SLURM_CONF")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/etc/slurm/slurm.conf"));
+    fs::read_to_string(conf)
+        .ok()
+        .and_then(|contents| cluster_name_from_conf(&contents))
+        .unwrap_or_else(|| DEFAULT_CLUSTER.to_string())
+}
+
+/// Finds the `ClusterName=` setting in the contents of a `slurm.conf`
+fn cluster_name_from_conf(contents: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let line = line.split('#').next()?.trim();
+        let (key, value) = line.split_once('=')?;
+        (key.trim().eq_ignore_ascii_case("ClusterName") && !value.trim().is_empty())
+            .then(|| value.trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_dir_prefers_xdg_state_home() {
+        let dir = default_state_dir(
+            Some(PathBuf::from("/xdg/state")),
+            Some(PathBuf::from("/home/me")),
+            "hpc",
+            "me",
+        );
+        assert_eq!(dir, Some(PathBuf::from("/xdg/state/jobs_done/hpc/me")));
+    }
+
+    #[test]
+    fn default_dir_falls_back_to_home() {
+        let dir = default_state_dir(
+            Some(PathBuf::from("relative")),
+            Some(PathBuf::from("/home/me")),
+            "hpc",
+            "../other",
+        );
+        assert_eq!(
+            dir,
+            Some(PathBuf::from(
+                "/home/me/.local/state/jobs_done/hpc/.._other"
+            ))
+        );
+        assert_eq!(default_state_dir(None, None, "hpc", "me"), None);
+    }
+
+    #[test]
+    fn cluster_name_is_read_from_slurm_conf() {
+        let conf = "\
+# ClusterName=commented
+SlurmctldHost=head01
+ClusterName=hpc-cluster # trailing comment
+";
+        assert_eq!(
+            cluster_name_from_conf(conf),
+            Some("hpc-cluster".to_string())
+        );
+        assert_eq!(cluster_name_from_conf("SlurmctldHost=head01"), None);
+    }
+
+    #[test]
+    fn atomic_write_replaces_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DATE_FILE);
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateDir::resolve(Some(dir.path()), "me").unwrap();
+        let guard = state.lock().unwrap();
+
+        let other = File::open(dir.path().join(LOCK_FILE)).unwrap();
+        assert!(FileExt::try_lock(&other).is_err());
+        drop(guard);
+        assert!(FileExt::try_lock(&other).is_ok());
+    }
+
+    #[test]
+    fn migrates_old_state_once() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        fs::write(old.path().join(DATE_FILE), "2023-04-22 16:15:05").unwrap();
+        fs::write(old.path().join(LEGACY_LOG_FILE), "old log").unwrap();
+
+        let state = StateDir::resolve(Some(new.path()), "me").unwrap();
+        assert!(state.migrate_from(old.path()).unwrap());
+        assert_eq!(
+            fs::read_to_string(state.date_file()).unwrap(),
+            "2023-04-22 16:15:05"
+        );
+        assert!(state.legacy_log_file().exists());
+        assert!(!state.history_file().exists());
+        // the originals stay where they were
+        assert!(old.path().join(DATE_FILE).exists());
+
+        // existing state is never overwritten
+        fs::write(old.path().join(DATE_FILE), "2024-01-01 00:00:00").unwrap();
+        assert!(!state.migrate_from(old.path()).unwrap());
+        assert_eq!(
+            fs::read_to_string(state.date_file()).unwrap(),
+            "2023-04-22 16:15:05"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use colored::{ColoredString, Colorize};
+use std::cmp::Reverse;
+
+use crate::FREE_TEXT_FIELDS;
+use crate::columns::Column;
+
+/// Space between two columns of the table
+const COLUMN_GAP: &str = "  ";
+/// Free-text columns such as the job name are never truncated below this many characters; if
+/// the table still doesn't fit, it's shown as records instead
+const MIN_TEXT_WIDTH: usize = 8;
+const ELLIPSIS: char = '…';
+
+/// Width of the terminal stdout is connected to, if it is one
+pub(crate) fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(width, _)| usize::from(width.0))
+}
+
+/// One line of the table: a job, or the header of a job array
+struct Row {
+    /// Levels of nesting below a job array header
+    depth: usize,
+    cells: Vec<ColoredString>,
+    /// Free-form lines shown under the row, outside the columns
+    details: Vec<String>,
+}
+
+/// Job table whose column widths follow its contents
+pub(crate) struct Table<'a> {
+    columns: &'a [Column],
+    rows: Vec<Row>,
+}
+
+impl<'a> Table<'a> {
+    pub(crate) fn new(columns: &'a [Column]) -> Self {
+        Table {
+            columns,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row with one cell per column, nested `depth` levels deep
+    pub(crate) fn push(&mut self, depth: usize, cells: impl IntoIterator<Item = ColoredString>) {
+        let cells = cells.into_iter().collect();
+        self.rows.push(Row {
+            depth,
+            cells,
+            details: Vec::new(),
+        });
+    }
+
+    /// Adds lines to show under the last row
+    pub(crate) fn push_details(&mut self, details: impl IntoIterator<Item = String>) {
+        if let Some(row) = self.rows.last_mut() {
+            row.details.extend(details);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Lays out the header and every row. With a `max_width`, free-text columns such as the job
+    /// name are truncated to fit it, and if that isn't enough each row is shown as a block of `header: value` lines.
+    pub(crate) fn render(&self, max_width: Option<usize>) -> Vec<String> {
+        match self.fit(self.content_widths(), max_width) {
+            Some(widths) => {
+                let mut lines = vec![self.header_line(&widths)];
+                lines.extend(self.row_lines(&widths));
+                lines
+            }
+            None => self.render_records(),
+        }
+    }
+
+    /// Shrinks the free-text columns of `widths`, widest first, so the table fits in `max_width`.
+    /// `None` if it can't be made to fit.
+    fn fit(&self, mut widths: Vec<usize>, max_width: Option<usize>) -> Option<Vec<usize>> {
+        let Some(max_width) = max_width else {
+            return Some(widths);
+        };
+        let total =
+            widths.iter().sum::<usize>() + COLUMN_GAP.len() * widths.len().saturating_sub(1);
+        let mut overflow = total.saturating_sub(max_width);
+        if overflow == 0 {
+            return Some(widths);
+        }
+
+        let mut text_columns: Vec<_> = (0..self.columns.len())
+            .filter(|&i| FREE_TEXT_FIELDS.contains(&self.columns[i].name.as_str()))
+            .collect();
+        text_columns.sort_by_key(|&i| Reverse(widths[i]));
+        for i in text_columns {
+            let min_width = MIN_TEXT_WIDTH.max(self.columns[i].header.chars().count());
+            let shrink = overflow.min(widths[i].saturating_sub(min_width));
+            widths[i] -= shrink;
+            overflow -= shrink;
+            if overflow == 0 {
+                return Some(widths);
+            }
+        }
+        None
+    }
+
+    /// Width of the widest header or cell of every column, including the indentation of nested
+    /// rows in the first column
+    fn content_widths(&self) -> Vec<usize> {
+        self.columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| {
+                        let indent = if i == 0 { indent(row.depth).len() } else { 0 };
+                        row.cells.get(i).map(|cell| indent + cell.chars().count())
+                    })
+                    .chain([column.header.chars().count()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    fn header_line(&self, widths: &[usize]) -> String {
+        let header = self
+            .columns
+            .iter()
+            .map(|column| column.header.bold())
+            .collect::<Vec<_>>();
+        format_line(&header, widths, "")
+    }
+
+    fn row_lines(&self, widths: &[usize]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for row in &self.rows {
+            lines.push(format_line(&row.cells, widths, &indent(row.depth)));
+            lines.extend(format_details(row));
+        }
+        lines
+    }
+
+    fn render_records(&self) -> Vec<String> {
+        let header_width = self
+            .columns
+            .iter()
+            .map(|column| column.header.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut lines = Vec::new();
+        let mut previous_depth = None;
+        for row in &self.rows {
+            // the first nested row stays with its array header, other records are set apart
+            if previous_depth.is_some_and(|depth| row.depth <= depth) {
+                lines.push(String::new());
+            }
+            previous_depth = Some(row.depth);
+            let indent = indent(row.depth);
+            for (column, cell) in self.columns.iter().zip(&row.cells) {
+                if cell.is_empty() {
+                    continue;
+                }
+                let header = format!("{:<header_width$}", column.header);
+                lines.push(format!("{indent}{}  {cell}", header.bold()));
+            }
+            lines.extend(format_details(row));
+        }
+        lines
+    }
+}
+
+/// Keeps tables that are printed one after the other, such as the batches of `watch`, aligned
+/// with each other. Columns only ever grow, and the header is repeated whenever they do.
+#[derive(Debug, Default)]
+pub(crate) struct StreamLayout {
+    widths: Vec<usize>,
+}
+
+impl StreamLayout {
+    /// Lays out the rows of `table` like [`Table::render`], with a header only if the columns
+    /// changed since the previous table
+    pub(crate) fn render(&mut self, table: &Table, max_width: Option<usize>) -> Vec<String> {
+        let previous = self.widths.iter().copied().chain(std::iter::repeat(0));
+        let widths: Vec<_> = table
+            .content_widths()
+            .into_iter()
+            .zip(previous)
+            .map(|(width, previous)| width.max(previous))
+            .collect();
+        let Some(fitted) = table.fit(widths.clone(), max_width) else {
+            // records need no header, but the next table that fits does
+            self.widths.clear();
+            return table.render_records();
+        };
+
+        let mut lines = Vec::new();
+        if widths != self.widths {
+            lines.push(table.header_line(&fitted));
+            self.widths = widths;
+        }
+        lines.extend(table.row_lines(&fitted));
+        lines
+    }
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+/// The details of `row`, indented a little further than the row itself
+fn format_details(row: &Row) -> impl Iterator<Item = String> + '_ {
+    let indent = indent(row.depth + 2);
+    row.details
+        .iter()
+        .map(move |detail| format!("{indent}{detail}"))
+}
+
+/// Pads every cell to its column's width, truncating it with an ellipsis if it's too long, and
+/// prefixes the first cell with `indent`
+fn format_line(cells: &[ColoredString], widths: &[usize], indent: &str) -> String {
+    let mut line = String::from(indent);
+    for (i, (cell, &width)) in cells.iter().zip(widths).enumerate() {
+        let width = if i == 0 { width - indent.len() } else { width };
+        let cell = truncate(cell, width);
+        let padding = width - cell.chars().count();
+        if i > 0 {
+            line.push_str(COLUMN_GAP);
+        }
+        line.push_str(&format!("{cell}{}", " ".repeat(padding)));
+    }
+    line.trim_end().to_string()
+}
+
+fn truncate(cell: &ColoredString, width: usize) -> ColoredString {
+    if cell.chars().count() <= width {
+        return cell.clone();
+    }
+    let mut truncated = cell.clone();
+    let kept: String = cell.chars().take(width.saturating_sub(1)).collect();
+    truncated.input = format!("{}{ELLIPSIS}", kept.trim_end());
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `line` without colours, which the environment (e.g. `CLICOLOR_FORCE`) may switch on
+    fn plain(line: &str) -> String {
+        let mut plain = String::new();
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                plain.push(c);
+            }
+        }
+        plain
+    }
+
+    fn columns() -> Vec<Column> {
+        ["jobid", "jobname", "state"]
+            .into_iter()
+            .map(Column::new)
+            .collect()
+    }
+
+    fn table(columns: &[Column]) -> Table<'_> {
+        let mut table = Table::new(columns);
+        table.push(
+            0,
+            ["12345".normal(), "short".normal(), "COMPLETED".normal()],
+        );
+        table.push(0, ["123456".normal(), "ArrayJob".normal(), "".normal()]);
+        table.push(
+            1,
+            [
+                "1000".normal(),
+                "ArrayJob".normal(),
+                "OUT_OF_MEMORY".normal(),
+            ],
+        );
+        table
+    }
+
+    #[test]
+    fn widths_follow_contents() {
+        let columns = columns();
+        let lines: Vec<_> = table(&columns)
+            .render(None)
+            .iter()
+            .map(|l| plain(l))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "Job ID  Job Name  State",
+                "12345   short     COMPLETED",
+                "123456  ArrayJob",
+                "  1000  ArrayJob  OUT_OF_MEMORY",
+            ]
+        );
+    }
+
+    #[test]
+    fn job_name_is_truncated_to_fit() {
+        let columns = columns();
+        let mut table = table(&columns);
+        table.push(
+            0,
+            [
+                "7".normal(),
+                "a rather long job name".normal(),
+                "FAILED".normal(),
+            ],
+        );
+        let lines: Vec<_> = table.render(Some(33)).iter().map(|l| plain(l)).collect();
+        assert!(lines.iter().all(|line| line.chars().count() <= 33));
+        assert_eq!(lines[4], "7       a rather…   FAILED");
+    }
+
+    #[test]
+    fn widest_text_column_is_truncated_first() {
+        let columns: Vec<_> = ["jobid", "workdir", "state"]
+            .into_iter()
+            .map(Column::new)
+            .collect();
+        let mut table = Table::new(&columns);
+        table.push(
+            0,
+            [
+                "7".normal(),
+                "/home/me/a/rather/long/path".normal(),
+                "FAILED".normal(),
+            ],
+        );
+        let lines: Vec<_> = table.render(Some(30)).iter().map(|l| plain(l)).collect();
+        assert!(lines.iter().all(|line| line.chars().count() <= 30));
+        assert_eq!(lines[1], "7       /home/me/a/ra…  FAILED");
+
+        let columns: Vec<_> = ["jobid", "jobname", "workdir"]
+            .into_iter()
+            .map(Column::new)
+            .collect();
+        let mut table = Table::new(&columns);
+        table.push(
+            0,
+            [
+                "7".normal(),
+                "a rather long job name".normal(),
+                "/home/me".normal(),
+            ],
+        );
+        let lines: Vec<_> = table.render(Some(30)).iter().map(|l| plain(l)).collect();
+        assert_eq!(lines[1], "7       a rather lo…  /home/me");
+        assert_eq!(table.render(Some(20)).len(), 3);
+    }
+
+    #[test]
+    fn details_follow_their_row() {
+        let columns = columns();
+        let mut table = table(&columns);
+        table.push_details(["last line of output".to_string()]);
+        let lines = table.render(None);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[4], "      last line of output");
+
+        let lines = table.render(Some(20));
+        assert_eq!(lines.last().unwrap(), "      last line of output");
+    }
+
+    #[test]
+    fn streamed_tables_repeat_the_header_when_columns_grow() {
+        let columns = columns();
+        let mut layout = StreamLayout::default();
+        let batch = |name: &str| {
+            let mut table = Table::new(&columns);
+            table.push(0, ["1".normal(), name.normal(), "FAILED".normal()]);
+            table
+        };
+        let render = |layout: &mut StreamLayout, name: &str| -> Vec<_> {
+            layout
+                .render(&batch(name), None)
+                .iter()
+                .map(|l| plain(l))
+                .collect()
+        };
+
+        assert_eq!(
+            render(&mut layout, "first"),
+            ["Job ID  Job Name  State", "1       first     FAILED"]
+        );
+        assert_eq!(render(&mut layout, "second"), ["1       second    FAILED"]);
+        assert_eq!(
+            render(&mut layout, "much longer"),
+            ["Job ID  Job Name     State", "1       much longer  FAILED"]
+        );
+        assert_eq!(
+            render(&mut layout, "short"),
+            ["1       short        FAILED"]
+        );
+    }
+
+    #[test]
+    fn narrow_terminals_get_records() {
+        let columns = columns();
+        let lines: Vec<_> = table(&columns)
+            .render(Some(20))
+            .iter()
+            .map(|l| plain(l))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "Job ID    12345",
+                "Job Name  short",
+                "State     COMPLETED",
+                "",
+                "Job ID    123456",
+                "Job Name  ArrayJob",
+                "  Job ID    1000",
+                "  Job Name  ArrayJob",
+                "  State     OUT_OF_MEMORY",
+            ]
+        );
+    }
+}
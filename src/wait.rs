@@ -0,0 +1,419 @@
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::backend::Backend;
+use crate::columns::{self, Column};
+use crate::job_state::JobState;
+use crate::output::OutputFormat;
+use crate::ranges::{Ranges, parse_ranges};
+use crate::watch::next_delay;
+use crate::{Details, Job, TableOptions, parse_jobs, print_jobs};
+
+/// Exit code when at least one job failed, was cancelled or otherwise didn't complete
+pub(crate) const EXIT_FAILED: i32 = 2;
+/// Exit code when jobs only fell short by reaching their time limit
+pub(crate) const EXIT_TIME_LIMIT: i32 = 3;
+/// Exit code when `--timeout` expired before every job finished, as with coreutils' `timeout`
+pub(crate) const EXIT_GAVE_UP: i32 = 124;
+/// How long a job may be missing from sacct before it's taken for a mistyped job ID, as
+/// slurmdbd records a job only some time after it was submitted
+const SUBMIT_GRACE: Duration = Duration::from_secs(60);
+
+/// A job or array task that sacct doesn't know (yet)
+#[derive(Debug)]
+struct NotFound(String);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// A job, array task or set of array tasks to wait for
+#[derive(Debug, PartialEq, Eq)]
+struct JobSpec {
+    base: usize,
+    /// Component of a heterogeneous job to wait for; `None` waits for every component
+    het_offset: Option<usize>,
+    /// Array tasks to wait for; `None` waits for the job or every task of the array job
+    indices: Option<Ranges>,
+}
+
+impl JobSpec {
+    /// Parses `12345`, `12345_7`, `12345_[1-10]` or `12345+1`
+    fn parse(value: &str) -> Result<Self> {
+        let invalid = || format!("invalid job ID {value:?}");
+        let (base, het_offset) = match value.split_once('+') {
+            Some((base, offset)) => (base, Some(offset.parse().with_context(invalid)?)),
+            None => (value, None),
+        };
+        let (base, indices) = match base.split_once('_') {
+            // Slurm doesn't allow heterogeneous job arrays
+            Some(_) if het_offset.is_some() => bail!(invalid()),
+            Some((base, indices)) => (base, Some(parse_ranges(indices)?)),
+            None => (base, None),
+        };
+        let base = base.parse().with_context(invalid)?;
+        Ok(JobSpec {
+            base,
+            het_offset,
+            indices,
+        })
+    }
+
+    fn matches(&self, job: &Job) -> bool {
+        job.jobid_base == self.base
+            && self
+                .het_offset
+                .is_none_or(|offset| job.het_offset == Some(offset))
+            && match (&self.indices, job.array_index) {
+                (None, _) => true,
+                (Some(indices), Some(index)) => indices.contains(index),
+                (Some(_), None) => false,
+            }
+    }
+
+    /// Whether every job this refers to has finished. `has_pending_tasks` tells whether sacct
+    /// still lists array tasks of this job that haven't started yet.
+    fn is_done(&self, jobs: &[Job], has_pending_tasks: bool) -> Result<bool> {
+        let matching: Vec<_> = jobs.iter().filter(|job| self.matches(job)).collect();
+        if !matching.iter().all(|job| job.is_finished()) {
+            return Ok(false);
+        }
+        match &self.indices {
+            None if matching.is_empty() && !has_pending_tasks => {
+                let jobid = match self.het_offset {
+                    Some(offset) => format!("{}+{offset}", self.base),
+                    None => self.base.to_string(),
+                };
+                Err(NotFound(format!("no job {jobid} found")).into())
+            }
+            None => Ok(!has_pending_tasks),
+            Some(indices) => {
+                let found: BTreeSet<_> =
+                    matching.iter().filter_map(|job| job.array_index).collect();
+                let missing = indices.iter().find(|index| !found.contains(index));
+                match missing {
+                    None => Ok(true),
+                    Some(_) if has_pending_tasks => Ok(false),
+                    Some(index) => {
+                        Err(NotFound(format!("no array task {}_{index} found", self.base)).into())
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Queries every job in `specs` once. Returns the jobs they refer to and whether all of them
+/// have finished.
+fn poll(backend: &dyn Backend, specs: &[JobSpec], fields: &[&str]) -> Result<(Vec<Job>, bool)> {
+    let bases: BTreeSet<_> = specs.iter().map(|spec| spec.base).collect();
+    let mut jobs = Vec::new();
+    let mut all_done = true;
+    for base in bases {
+        let output = backend.job_details(&base.to_string(), fields)?;
+        let base_jobs = parse_jobs(&output, fields)?;
+        let has_pending_tasks = base_jobs.iter().any(|job| job.pending_tasks.is_some());
+
+        for spec in specs.iter().filter(|spec| spec.base == base) {
+            all_done &= spec.is_done(&base_jobs, has_pending_tasks)?;
+        }
+        jobs.extend(
+            base_jobs
+                .into_iter()
+                .filter(|job| specs.iter().any(|spec| spec.matches(job))),
+        );
+    }
+    Ok((jobs, all_done))
+}
+
+/// Exit code summarizing how the finished `jobs` ended. Without any jobs, none of them
+/// completed.
+fn exit_code(jobs: &[Job]) -> i32 {
+    if jobs.is_empty() {
+        EXIT_FAILED
+    } else if jobs.iter().all(|job| job.state == JobState::Completed) {
+        0
+    } else if jobs
+        .iter()
+        .all(|job| matches!(job.state, JobState::Completed | JobState::Timeout))
+    {
+        EXIT_TIME_LIMIT
+    } else {
+        EXIT_FAILED
+    }
+}
+
+/// Polls until every job in `jobids` has finished, or `timeout` expires, then prints a summary.
+/// Returns the exit code for the process.
+pub(crate) fn run(
+    backend: &dyn Backend,
+    jobids: &[String],
+    columns: &[Column],
+    output: OutputFormat,
+    options: &TableOptions,
+    interval: Duration,
+    timeout: Option<Duration>,
+) -> Result<i32> {
+    let specs = jobids
+        .iter()
+        .map(|jobid| JobSpec::parse(jobid))
+        .collect::<Result<Vec<_>>>()?;
+    if specs.is_empty() {
+        bail!("no jobs to wait for");
+    }
+    let fields = columns::sacct_fields(columns);
+    let started = Instant::now();
+    let deadline = timeout.map(|timeout| started + timeout);
+
+    let mut jobs = Vec::new();
+    let mut failures = 0;
+    let mut first_poll = true;
+    let finished = loop {
+        let timer = Instant::now();
+        match poll(backend, &specs, &fields) {
+            Ok((polled, done)) => {
+                jobs = polled;
+                failures = 0;
+                if done {
+                    break true;
+                }
+            }
+            // a job that was only just submitted, or else most likely a mistyped job ID, which
+            // waiting won't fix
+            Err(err) if err.is::<NotFound>() => {
+                if started.elapsed() >= SUBMIT_GRACE {
+                    return Err(err);
+                }
+            }
+            Err(err) if first_poll => return Err(err),
+            Err(err) => {
+                failures += 1;
+                eprintln!("{} {err:#}", "warning:".yellow().bold());
+            }
+        }
+        first_poll = false;
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break false;
+        }
+
+        let mut delay = next_delay(interval, timer.elapsed(), failures);
+        if let Some(deadline) = deadline {
+            delay = delay.min(deadline.saturating_duration_since(Instant::now()));
+        }
+        std::thread::sleep(delay);
+    };
+
+    let heading = if finished {
+        "Finished jobs"
+    } else {
+        "Gave up waiting for jobs"
+    };
+    print_jobs(
+        output,
+        columns,
+        &jobs,
+        &Details::new(),
+        options,
+        &heading.bold().underline().to_string(),
+        &heading.bold().underline().to_string(),
+    )?;
+    Ok(if finished {
+        exit_code(&jobs)
+    } else {
+        EXIT_GAVE_UP
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FORMAT_CMD;
+    use crate::backend::{Replay, replay_fixtures};
+    use chrono::NaiveDateTime;
+    use std::cell::Cell;
+
+    fn columns() -> Vec<Column> {
+        FORMAT_CMD.into_iter().map(Column::new).collect()
+    }
+
+    fn specs(jobids: &[&str]) -> Vec<JobSpec> {
+        jobids
+            .iter()
+            .map(|id| JobSpec::parse(id).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn job_specs() {
+        assert_eq!(
+            JobSpec::parse("12346_[1-3,7]").unwrap(),
+            JobSpec {
+                base: 12346,
+                het_offset: None,
+                indices: Some([1, 2, 3, 7].into_iter().collect())
+            }
+        );
+        assert_eq!(JobSpec::parse("12345").unwrap().indices, None);
+        assert_eq!(JobSpec::parse("12345+1").unwrap().het_offset, Some(1));
+        assert!(JobSpec::parse("abc").is_err());
+        assert!(JobSpec::parse("123_x").is_err());
+        assert!(JobSpec::parse("123+x").is_err());
+        assert!(JobSpec::parse("123_1+1").is_err());
+    }
+
+    #[test]
+    fn finished_jobs_are_done() {
+        let (jobs, done) = poll(
+            &replay_fixtures(),
+            &specs(&["56938942", "56938944_[1-2]"]),
+            &FORMAT_CMD,
+        )
+        .unwrap();
+        assert!(done);
+        let ids: Vec<_> = jobs.iter().map(Job::jobid_display).collect();
+        assert_eq!(ids, ["56938942", "56938944_1", "56938944_2"]);
+        assert_eq!(exit_code(&jobs), EXIT_TIME_LIMIT);
+        assert_eq!(exit_code(&jobs[..2]), 0);
+        assert_eq!(exit_code(&[]), EXIT_FAILED);
+    }
+
+    #[test]
+    fn running_and_pending_jobs_are_not_done() {
+        for jobid in ["56938945", "56938946"] {
+            let (_, done) = poll(&replay_fixtures(), &specs(&[jobid]), &FORMAT_CMD).unwrap();
+            assert!(!done, "{jobid}");
+        }
+    }
+
+    #[test]
+    fn failures_take_precedence() {
+        let (jobs, _) = poll(
+            &replay_fixtures(),
+            &specs(&["56938943", "56938944"]),
+            &FORMAT_CMD,
+        )
+        .unwrap();
+        assert_eq!(exit_code(&jobs), EXIT_FAILED);
+    }
+
+    #[test]
+    fn unknown_jobs_are_errors() {
+        for jobid in ["1", "56938944_3"] {
+            let err = poll(&replay_fixtures(), &specs(&[jobid]), &FORMAT_CMD).unwrap_err();
+            assert!(err.is::<NotFound>(), "{jobid}");
+        }
+    }
+
+    /// Answers the first query like the fixtures, then fails every query after it
+    struct Flaky {
+        replay: Replay,
+        queries: Cell<u32>,
+    }
+
+    impl Backend for Flaky {
+        fn query_jobs(
+            &self,
+            fields: &[&str],
+            window_start: NaiveDateTime,
+            user: &str,
+        ) -> Result<String> {
+            self.replay.query_jobs(fields, window_start, user)
+        }
+
+        fn job_details(&self, jobid: &str, fields: &[&str]) -> Result<String> {
+            self.queries.set(self.queries.get() + 1);
+            if self.queries.get() > 1 {
+                bail!("unable to contact slurmdbd");
+            }
+            self.replay.job_details(jobid, fields)
+        }
+
+        fn batch_script(&self, jobid: &str) -> Result<Option<String>> {
+            self.replay.batch_script(jobid)
+        }
+    }
+
+    #[test]
+    fn timeout_applies_while_queries_fail() {
+        let backend = Flaky {
+            replay: replay_fixtures(),
+            queries: Cell::new(0),
+        };
+        let code = run(
+            &backend,
+            &["56938945".to_string()],
+            &columns(),
+            OutputFormat::Ndjson,
+            &TableOptions::default(),
+            Duration::from_millis(10),
+            Some(Duration::from_millis(100)),
+        )
+        .unwrap();
+        assert_eq!(code, EXIT_GAVE_UP);
+        assert!(backend.queries.get() > 1);
+    }
+
+    #[test]
+    fn jobs_not_recorded_yet_are_waited_for() {
+        let code = run(
+            &replay_fixtures(),
+            &["1".to_string()],
+            &columns(),
+            OutputFormat::Ndjson,
+            &TableOptions::default(),
+            Duration::from_millis(10),
+            Some(Duration::from_millis(50)),
+        )
+        .unwrap();
+        assert_eq!(code, EXIT_GAVE_UP);
+    }
+
+    #[test]
+    fn pending_array_tasks_are_waited_for() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("jobs.sacct"),
+            "JobID|JobName|AllocCPUS|Elapsed|Start|End|State\n\
+             77_1|a|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED\n\
+             77_[2-3]|a|1|00:00:00|Unknown|Unknown|PENDING\n",
+        )
+        .unwrap();
+        let backend = Replay::new(dir.path());
+        for jobid in ["77", "77_[1-3]"] {
+            let (_, done) = poll(&backend, &specs(&[jobid]), &FORMAT_CMD).unwrap();
+            assert!(!done, "{jobid}");
+        }
+        let (_, done) = poll(&backend, &specs(&["77_1"]), &FORMAT_CMD).unwrap();
+        assert!(done);
+    }
+
+    #[test]
+    fn heterogeneous_job_components_are_waited_for() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("jobs.sacct"),
+            "JobID|JobName|AllocCPUS|Elapsed|Start|End|State\n\
+             88+0|a|1|00:01:00|2023-04-22T16:15:05|2023-04-22T16:16:05|COMPLETED\n\
+             88+1|a|1|00:00:30|2023-04-22T16:15:05|Unknown|RUNNING\n",
+        )
+        .unwrap();
+        let backend = Replay::new(dir.path());
+        let (jobs, done) = poll(&backend, &specs(&["88+0"]), &FORMAT_CMD).unwrap();
+        assert!(done);
+        let ids: Vec<_> = jobs.iter().map(Job::jobid_display).collect();
+        assert_eq!(ids, ["88+0"]);
+        for jobid in ["88", "88+1"] {
+            let (_, done) = poll(&backend, &specs(&[jobid]), &FORMAT_CMD).unwrap();
+            assert!(!done, "{jobid}");
+        }
+        let err = poll(&backend, &specs(&["88+2"]), &FORMAT_CMD).unwrap_err();
+        assert!(err.is::<NotFound>());
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{Context, Result, bail};
+use chrono::Local;
+use colored::Colorize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::backend::Backend;
+use crate::columns::{self, Column};
+use crate::hooks::{self, Hook};
+use crate::notify::{self, Webhook};
+use crate::output::{self, OutputFormat};
+use crate::state::StateDir;
+use crate::table::{self, StreamLayout, Table};
+use crate::{Job, JobKey, START_END_FORMAT, collect_jobs, job_cells, log_jobs, open_history};
+
+/// A slow sacct query pushes the next poll out to this many times the query's duration, so
+/// `watch` doesn't keep a struggling accounting database busy
+const SLOW_QUERY_FACTOR: u32 = 4;
+/// Polls are never delayed by more than this many intervals
+const MAX_BACKOFF_FACTOR: u32 = 10;
+/// How often a sleeping watch checks whether it was asked to stop
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Delay before the next poll: `interval`, stretched when the last query took long or the last
+/// `failures` queries in a row failed
+pub(crate) fn next_delay(interval: Duration, query_time: Duration, failures: u32) -> Duration {
+    let slow = query_time.saturating_mul(SLOW_QUERY_FACTOR);
+    let failed = interval.saturating_mul(2_u32.saturating_pow(failures));
+    interval
+        .max(slow)
+        .max(failed)
+        .min(interval.saturating_mul(MAX_BACKOFF_FACTOR))
+}
+
+/// Turns the jobs found by each poll into output lines, reporting every job only once
+struct Watcher<'a> {
+    columns: &'a [Column],
+    output: OutputFormat,
+    reported: HashSet<JobKey>,
+    layout: StreamLayout,
+    header_printed: bool,
+}
+
+impl<'a> Watcher<'a> {
+    fn new(columns: &'a [Column], output: OutputFormat) -> Result<Self> {
+        if output == OutputFormat::Json {
+            bail!("watch can't stream a single JSON document, use --output ndjson instead");
+        }
+        Ok(Watcher {
+            columns,
+            output,
+            reported: HashSet::new(),
+            layout: StreamLayout::default(),
+            header_printed: false,
+        })
+    }
+
+    /// Lines for the finished `jobs` that haven't been reported before
+    fn report(&mut self, jobs: &[Job], max_width: Option<usize>) -> Result<Vec<String>> {
+        let new: Vec<_> = jobs
+            .iter()
+            .filter(|job| job.is_displayable())
+            .filter(|job| self.reported.insert(job.key()))
+            .collect();
+        if new.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let delimiter = match self.output {
+            OutputFormat::Table => {
+                let mut table = Table::new(self.columns);
+                for job in new {
+                    table.push(0, job_cells(&job.jobid_display(), job, self.columns));
+                }
+                return Ok(self.layout.render(&table, max_width));
+            }
+            OutputFormat::Json | OutputFormat::Ndjson => return output::render_ndjson(new),
+            OutputFormat::Csv => ',',
+            OutputFormat::Tsv => '\t',
+        };
+        let mut rows = output::render_delimited(new, self.columns, delimiter);
+        if std::mem::replace(&mut self.header_printed, true) {
+            rows.remove(0);
+        }
+        Ok(rows)
+    }
+}
+
+/// Records `jobs` in the history, like a regular run does
+fn record(state_dir: &StateDir, jobs: &[Job]) -> Result<()> {
+    let _lock = state_dir.lock()?;
+    let mut history = open_history(&state_dir.history_file(), &state_dir.legacy_log_file())?;
+    log_jobs(jobs, &mut history);
+    history.save()
+}
+
+/// Sleeps for `delay`, waking up early if `stop` is set
+fn sleep_unless_stopped(delay: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + delay;
+    while !stop.load(Ordering::SeqCst) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(STOP_CHECK_INTERVAL));
+    }
+}
+
+fn warn(err: &anyhow::Error) {
+    eprintln!("{} {err:#}", "warning:".yellow().bold());
+}
+
+/// Polls for jobs finishing from now on every `interval` and prints each one as it's found, until
+/// interrupted with Ctrl-C
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run(
+    backend: &dyn Backend,
+    state_dir: &StateDir,
+    user: &str,
+    states: &[String],
+    columns: &[Column],
+    output: OutputFormat,
+    interval: Duration,
+    hooks: &[Hook],
+    mut webhook: Option<&mut Webhook>,
+) -> Result<()> {
+    let mut watcher = Watcher::new(columns, output)?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))
+        .context("unable to install the Ctrl-C handler")?;
+
+    let fields = columns::sacct_fields(columns);
+    let mut window_start = Local::now().naive_local();
+    if output == OutputFormat::Table {
+        println!(
+            "{} {} (Ctrl-C to stop)",
+            "Watching for jobs finishing after".bold().underline(),
+            window_start.format(START_END_FORMAT).to_string().yellow()
+        );
+    }
+
+    let mut failures = 0;
+    while !stop.load(Ordering::SeqCst) {
+        let poll_start = Local::now().naive_local();
+        let timer = Instant::now();
+        match collect_jobs(backend, window_start, user, states, &fields) {
+            Ok(jobs) => {
+                failures = 0;
+                // sacct also returns jobs that were still running at the window's start, so
+                // anything finishing from here on is found by the next poll
+                window_start = poll_start;
+                // like a failed query, none of these should end the watch
+                if let Err(err) = record(state_dir, &jobs) {
+                    warn(&err);
+                }
+                for line in watcher.report(&jobs, table::terminal_width())? {
+                    println!("{line}");
+                }
+                if let Err(err) = hooks::run(backend, state_dir, hooks, &jobs) {
+                    warn(&err);
+                }
+                let notified = webhook
+                    .as_deref_mut()
+                    .map_or(Ok(()), |webhook| notify::notify(webhook, state_dir, &jobs));
+                if let Err(err) = notified {
+                    warn(&err);
+                }
+            }
+            // Ctrl-C interrupts a running sacct too
+            Err(_) if stop.load(Ordering::SeqCst) => break,
+            Err(err) => {
+                failures += 1;
+                warn(&err);
+            }
+        }
+        sleep_unless_stopped(next_delay(interval, timer.elapsed(), failures), &stop);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::replayed_jobs;
+
+    fn columns() -> Vec<Column> {
+        ["jobid", "state"].into_iter().map(Column::new).collect()
+    }
+
+    #[test]
+    fn delay_backs_off_for_slow_or_failing_queries() {
+        let interval = Duration::from_secs(60);
+        let fast = Duration::from_secs(1);
+        assert_eq!(next_delay(interval, fast, 0), interval);
+        assert_eq!(
+            next_delay(interval, Duration::from_secs(30), 0),
+            Duration::from_secs(120)
+        );
+        assert_eq!(next_delay(interval, fast, 2), Duration::from_secs(240));
+        assert_eq!(next_delay(interval, fast, 100), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn jobs_are_reported_once() {
+        let columns = columns();
+        let mut watcher = Watcher::new(&columns, OutputFormat::Table).unwrap();
+        let jobs = replayed_jobs();
+
+        let lines = watcher.report(&jobs, None).unwrap();
+        // a header and every finished job, but not the pending one
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with("56938942 "));
+        assert!(lines[4].starts_with("56938944_2 "));
+        assert!(watcher.report(&jobs, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn delimited_header_is_printed_once() {
+        let columns = columns();
+        let mut watcher = Watcher::new(&columns, OutputFormat::Csv).unwrap();
+        let jobs = replayed_jobs();
+
+        let lines = watcher.report(&jobs[..1], None).unwrap();
+        assert_eq!(lines, ["jobid,state", "56938942,COMPLETED"]);
+        let lines = watcher.report(&jobs[1..2], None).unwrap();
+        assert_eq!(lines, ["56938943,FAILED"]);
+    }
+
+    #[test]
+    fn json_cannot_be_streamed() {
+        assert!(Watcher::new(&columns(), OutputFormat::Json).is_err());
+    }
+}
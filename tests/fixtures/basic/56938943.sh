@@ -0,0 +1,6 @@
+#!/bin/bash
+#SBATCH --job-name="relax slab 2x2"
+#SBATCH --ntasks=4
+#SBATCH --time=01:00:00
+
+srun vasp_std
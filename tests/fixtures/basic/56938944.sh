@@ -0,0 +1,5 @@
+#!/bin/bash
+#SBATCH --job-name=ArrayJob
+#SBATCH --array=1-2
+
+python run.py "$SLURM_ARRAY_TASK_ID"